@@ -0,0 +1,90 @@
+//! Dns related members
+use core::net::Ipv4Addr;
+
+/// Longest host name the firmware resolves,
+/// including the null terminator
+pub const HOSTNAME_MAX_SIZE: usize = 64;
+/// Size of a dns response sent by the Atwinc1500
+pub(crate) const DNS_REPLY_SIZE: usize = HOSTNAME_MAX_SIZE + 4;
+/// Number of resolved hosts kept in the dns cache
+pub const DNS_CACHE_SIZE: usize = 4;
+/// Milliseconds a resolved host is kept by default.
+/// The firmware doesn't pass on the ttl of the record
+pub const DEFAULT_DNS_CACHE_TTL_MS: u32 = 60_000;
+
+/// A host resolved by the Atwinc1500
+#[derive(Copy, Clone)]
+struct DnsEntry {
+    host: [u8; HOSTNAME_MAX_SIZE],
+    len: usize,
+    ip: Ipv4Addr,
+    expires_ms: u64,
+}
+
+/// Fixed size cache of resolved hosts. When the
+/// cache is full the entry closest to expiring
+/// is replaced
+#[derive(Copy, Clone)]
+pub(crate) struct DnsCache {
+    entries: [Option<DnsEntry>; DNS_CACHE_SIZE],
+    ttl_ms: u32,
+}
+
+impl DnsCache {
+    /// Creates an empty DnsCache
+    pub fn new() -> Self {
+        Self {
+            entries: [None; DNS_CACHE_SIZE],
+            ttl_ms: DEFAULT_DNS_CACHE_TTL_MS,
+        }
+    }
+
+    /// Sets how long new entries are kept.
+    /// A ttl of 0 disables the cache
+    pub fn set_ttl(&mut self, ttl_ms: u32) {
+        self.ttl_ms = ttl_ms;
+    }
+
+    /// Adds a host resolved at now_ms. Host names
+    /// too long for the firmware are not cached
+    pub fn insert(&mut self, host: &[u8], ip: Ipv4Addr, now_ms: u64) {
+        if self.ttl_ms == 0 || host.is_empty() || host.len() >= HOSTNAME_MAX_SIZE {
+            return;
+        }
+        let mut entry = DnsEntry {
+            host: [0; HOSTNAME_MAX_SIZE],
+            len: host.len(),
+            ip,
+            expires_ms: now_ms + self.ttl_ms as u64,
+        };
+        entry.host[..host.len()].copy_from_slice(host);
+        // Reuse the slot of the same host, then an
+        // empty slot, then the oldest entry
+        let slot = self
+            .entries
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.host[..e.len] == *host))
+            .or_else(|| self.entries.iter().position(|e| e.is_none()))
+            .unwrap_or_else(|| {
+                (0..DNS_CACHE_SIZE)
+                    .min_by_key(|&i| self.entries[i].map_or(0, |e| e.expires_ms))
+                    .unwrap_or(0)
+            });
+        self.entries[slot] = Some(entry);
+    }
+
+    /// Gets the address of a host if
+    /// it hasn't expired at now_ms
+    pub fn get(&self, host: &[u8], now_ms: u64) -> Option<Ipv4Addr> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|e| e.host[..e.len] == *host && now_ms < e.expires_ms)
+            .map(|e| e.ip)
+    }
+
+    /// Removes every entry
+    pub fn clear(&mut self) {
+        self.entries = [None; DNS_CACHE_SIZE];
+    }
+}
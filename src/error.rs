@@ -4,8 +4,11 @@ use core::fmt;
 // Derives defmt::Format if building for bare metal
 // otherwise it does not derive defmt::Format
 // Unit tests get a linker error if this isn't done
-#[cfg_attr(target_os = "none", derive(Eq, PartialEq, Debug, defmt::Format))]
-#[cfg_attr(not(target_os = "none"), derive(Eq, PartialEq, Debug))]
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
 /// Atwinc1500 error types
 pub enum Error {
     /// Attempted to parse an invalid spi command
@@ -20,6 +23,151 @@ pub enum Error {
     /// Error received from the atwinc1500
     /// while trying to read from register
     SpiReadRegisterError,
+    /// A register access read back only 0x00 or
+    /// only 0xff, so nothing is driving MISO.
+    /// Check the wiring, chip select and power
+    SpiNoResponse,
+    /// Error received from the atwinc1500
+    /// while trying to write data
+    SpiWriteDataError,
+    /// The Atwinc1500 did not acknowledge
+    /// the command terminating a transfer
+    SpiTerminateError,
+    /// Error received from the atwinc1500
+    /// while trying to read data
+    SpiReadDataError,
+    /// The atwinc1500 was not ready to send
+    /// data within the configured read retries
+    SpiReadDataTimeout,
+    /// The Atwinc1500 did not accept
+    /// the request to disable crc
+    CrcDisableError,
+    /// The firmware did not finish initializing.
+    /// get_init_state shows how far it got
+    InitTimeout,
+    /// The Atwinc1500 clock did not
+    /// start when waking it from sleep
+    WakeTimeout,
+    /// The Atwinc1500 did not send
+    /// random bytes in time
+    RandomTimeout,
+    /// The spi flash inside the Atwinc1500
+    /// did not answer or returned an invalid id
+    FlashNotDetected,
+    /// The firmware requires a newer driver version
+    /// than the one in InitConfig::version_info
+    DriverVersionMismatch,
+    /// The Atwinc1500 failed to connect
+    /// to the requested network
+    ConnectionFailed,
+    /// The Atwinc1500 did not report a
+    /// connection result in time
+    ConnectionTimeout,
+    /// A scan is already in progress
+    ScanInProgress,
+    /// A dns resolve is already in progress
+    DnsInProgress,
+    /// A ping is already in progress
+    PingInProgress,
+    /// The requested index is out of range
+    IndexOutOfRange,
+    /// The value is not a valid wireless channel
+    InvalidChannel,
+    /// The value is not a known security type
+    InvalidSecurityType,
+    /// The value is not a valid country code
+    InvalidCountryCode,
+    /// The gpio pin does not support the request
+    InvalidGpio,
+    /// The value is not a socket command
+    InvalidSocketCommand,
+    /// A scan option is out of range
+    InvalidScanOptions,
+    /// The ssid is longer than 32 bytes
+    SsidTooLong,
+    /// The passphrase is longer than 64 bytes
+    PassphraseTooLong,
+    /// The host name is longer than 63 bytes
+    HostnameTooLong,
+    /// The Atwinc1500 could not resolve the host
+    HostNotFound,
+    /// Saved connection bytes are the wrong size,
+    /// an unknown version or hold invalid values
+    InvalidConnectionBytes,
+    /// Every tcp socket of the
+    /// Atwinc1500 is in use
+    NoFreeSockets,
+    /// The socket was freed by recover
+    /// or soft_reset
+    SocketNotOpen,
+    /// The peer closed the socket
+    /// or it failed
+    SocketClosed,
+    /// Bytes received on the socket were dropped
+    /// because its receive buffer was full
+    SocketBufferOverflow,
+    /// The Atwinc1500 could not complete
+    /// the tls handshake of a connect
+    TlsHandshakeFailed,
+    /// A socket option value is
+    /// out of the firmware's range
+    InvalidSocketOption,
+    /// The driver does not implement the request yet
+    NotImplemented,
+    /// The firmware on the Atwinc1500
+    /// does not support the request
+    UnsupportedFirmware,
+    /// Error in the host interface layer
+    HifError(HifError),
+}
+
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+/// Host interface error types
+pub enum HifError {
+    /// The length in an hif header does not
+    /// match the size of the data being sent
+    SizeMismatch,
+    /// The packet is larger than the
+    /// Atwinc1500 can receive
+    PacketTooLarge,
+    /// The Atwinc1500 did not allocate
+    /// a buffer for the packet
+    AllocationFailed,
+    /// A read falls outside the frame
+    /// being received
+    AddressMismatch,
+    /// A received frame has the wrong size
+    /// or values that aren't possible
+    InvalidFrame,
+    /// A received hif header has a length or
+    /// group id that isn't possible
+    InvalidHeader,
+}
+
+/// A failed spi transaction with the
+/// command and address it was sent with
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub struct SpiErrorContext {
+    /// Spi command byte
+    pub command: u8,
+    /// Register or memory address
+    pub address: u32,
+    /// Error the transaction failed with
+    pub error: Error,
+}
+
+impl From<HifError> for Error {
+    fn from(err: HifError) -> Self {
+        Error::HifError(err)
+    }
 }
 
 impl fmt::Display for Error {
@@ -30,6 +178,64 @@ impl fmt::Display for Error {
             Error::SpiTransferError => write!(f, "Spi Transfer Error"),
             Error::SpiWriteRegisterError => write!(f, "Error writing to register"),
             Error::SpiReadRegisterError => write!(f, "Error reading from register"),
+            Error::SpiNoResponse => write!(f, "No response on the spi bus"),
+            Error::SpiWriteDataError => write!(f, "Error writing data"),
+            Error::SpiTerminateError => write!(f, "Error terminating transfer"),
+            Error::SpiReadDataError => write!(f, "Error reading data"),
+            Error::SpiReadDataTimeout => write!(f, "Timed out reading data"),
+            Error::CrcDisableError => write!(f, "Failed to disable crc"),
+            Error::InitTimeout => write!(f, "Timed out initializing firmware"),
+            Error::WakeTimeout => write!(f, "Timed out waking chip"),
+            Error::RandomTimeout => write!(f, "Timed out reading random bytes"),
+            Error::FlashNotDetected => write!(f, "Flash not detected"),
+            Error::DriverVersionMismatch => {
+                write!(f, "Firmware requires a newer driver version")
+            }
+            Error::ConnectionFailed => write!(f, "Failed to connect to network"),
+            Error::ConnectionTimeout => write!(f, "Timed out connecting to network"),
+            Error::ScanInProgress => write!(f, "Scan in progress"),
+            Error::DnsInProgress => write!(f, "Dns resolve in progress"),
+            Error::PingInProgress => write!(f, "Ping in progress"),
+            Error::IndexOutOfRange => write!(f, "Index out of range"),
+            Error::InvalidChannel => write!(f, "Invalid channel"),
+            Error::InvalidSecurityType => write!(f, "Invalid security type"),
+            Error::InvalidCountryCode => write!(f, "Invalid country code"),
+            Error::InvalidGpio => write!(f, "Unsupported by gpio pin"),
+            Error::InvalidSocketCommand => write!(f, "Invalid socket command"),
+            Error::InvalidScanOptions => write!(f, "Invalid scan options"),
+            Error::SsidTooLong => write!(f, "Ssid is too long"),
+            Error::PassphraseTooLong => write!(f, "Passphrase is too long"),
+            Error::HostnameTooLong => write!(f, "Host name is too long"),
+            Error::HostNotFound => write!(f, "Host not found"),
+            Error::InvalidConnectionBytes => write!(f, "Invalid saved connection"),
+            Error::NoFreeSockets => write!(f, "No free sockets"),
+            Error::SocketNotOpen => write!(f, "Socket is not open"),
+            Error::SocketClosed => write!(f, "Socket was closed"),
+            Error::SocketBufferOverflow => write!(f, "Socket receive buffer overflowed"),
+            Error::TlsHandshakeFailed => write!(f, "Tls handshake failed"),
+            Error::InvalidSocketOption => write!(f, "Invalid socket option"),
+            Error::NotImplemented => write!(f, "Not implemented"),
+            Error::UnsupportedFirmware => write!(f, "Unsupported by firmware"),
+            Error::HifError(ref e) => write!(f, "Host interface error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HifError {}
+
+impl fmt::Display for HifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HifError::SizeMismatch => write!(f, "Header length does not match data size"),
+            HifError::PacketTooLarge => write!(f, "Packet too large"),
+            HifError::AllocationFailed => write!(f, "Failed to allocate buffer"),
+            HifError::AddressMismatch => write!(f, "Read outside of received frame"),
+            HifError::InvalidFrame => write!(f, "Invalid frame received"),
+            HifError::InvalidHeader => write!(f, "Invalid hif header received"),
         }
     }
 }
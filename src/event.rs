@@ -0,0 +1,112 @@
+//! Events reported by the Atwinc1500
+
+/// Number of events that can be waiting
+/// to be read with next_event
+const EVENT_QUEUE_SIZE: usize = 8;
+
+/// An event sent by the Atwinc1500
+/// and processed by handle_events
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub enum Event {
+    /// An ip address was assigned by dhcp
+    /// for the first time since connecting
+    IpConfigured,
+    /// The dhcp lease was renewed and the
+    /// ip configuration was updated
+    DhcpRenewed,
+    /// The ip configuration was lost
+    /// because the network disconnected
+    DhcpLost,
+    /// A scan finished. num_ap is the number of
+    /// networks found and may be 0
+    ScanDone {
+        /// Number of networks found
+        num_ap: u8,
+    },
+    /// Connection info arrived and can
+    /// be read with get_connection_info
+    ConnectionInfo,
+    /// Random bytes arrived and can be
+    /// read with get_random_bytes
+    RandomBytes,
+    /// A dns resolve finished and its address
+    /// can be read with get_resolved_address
+    DnsResolved,
+    /// A ping finished and its result can
+    /// be read with get_ping_result
+    PingDone,
+    /// A scan result arrived and can
+    /// be read with get_scan_result
+    ScanResult {
+        /// Index of the scan result
+        index: u8,
+    },
+    /// A socket received data, or a listening
+    /// socket has a connection to accept
+    SocketReadable {
+        /// Socket handle
+        handle: u8,
+    },
+    /// A socket connected or finished sending
+    SocketWritable {
+        /// Socket handle
+        handle: u8,
+    },
+    /// A socket was closed by the peer
+    /// or failed with an error
+    SocketClosed {
+        /// Socket handle
+        handle: u8,
+    },
+}
+
+/// Fixed size queue of events waiting to be read.
+/// When the queue is full the oldest event is dropped
+pub(crate) struct EventQueue {
+    events: [Option<Event>; EVENT_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl EventQueue {
+    /// Creates an empty EventQueue
+    pub fn new() -> Self {
+        Self {
+            events: [None; EVENT_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds an event to the back of the queue
+    pub fn push(&mut self, event: Event) {
+        let tail = (self.head + self.len) % EVENT_QUEUE_SIZE;
+        self.events[tail] = Some(event);
+        if self.len == EVENT_QUEUE_SIZE {
+            self.head = (self.head + 1) % EVENT_QUEUE_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Removes the event at the front of the queue
+    pub fn pop(&mut self) -> Option<Event> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % EVENT_QUEUE_SIZE;
+        self.len -= 1;
+        event
+    }
+
+    /// Number of events in the queue
+    #[cfg(feature = "test-internals")]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
@@ -0,0 +1,61 @@
+//! Reference implementations built on the driver's
+//! public socket api, for checking a board or
+//! comparing against an application's own loop
+use crate::error::Error;
+use crate::Atwinc1500;
+use embedded_hal::blocking::{delay::DelayMs, spi::Transfer};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+impl<SPI, D, O, I> Atwinc1500<SPI, D, O, I>
+where
+    SPI: Transfer<u8>,
+    D: DelayMs<u32>,
+    O: OutputPin,
+    I: InputPin,
+{
+    /// Runs a tcp echo server on port, sending every
+    /// byte a client sends back to it. Clients are
+    /// served one after another, a client that
+    /// disconnects is closed and the next one is
+    /// accepted. Returns after max_clients clients,
+    /// or only on an error if max_clients is None
+    pub fn run_echo_server(&mut self, port: u16, max_clients: Option<u32>) -> Result<(), Error> {
+        let listener = self.tcp_socket()?;
+        self.tcp_bind(&listener, port)?;
+        self.tcp_listen(&listener)?;
+        let mut clients: u32 = 0;
+        while max_clients.is_none_or(|max| clients < max) {
+            let client = match self.tcp_accept(&listener)? {
+                Some((client, _peer)) => client,
+                None => {
+                    self.delay.delay_ms(1);
+                    continue;
+                }
+            };
+            let mut buffer = [0u8; crate::socket::SOCKET_RX_BUFFER_SIZE];
+            'client: loop {
+                let received = match self.tcp_receive(&client, &mut buffer)? {
+                    // The client disconnected
+                    Some(0) => break,
+                    Some(received) => received,
+                    None => {
+                        self.delay.delay_ms(1);
+                        continue;
+                    }
+                };
+                let mut sent = 0;
+                while sent < received {
+                    match self.tcp_send(&client, &buffer[sent..received]) {
+                        Ok(Some(count)) => sent += count,
+                        Ok(None) => self.delay.delay_ms(1),
+                        Err(Error::SocketClosed) => break 'client,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            self.close_socket(client)?;
+            clients += 1;
+        }
+        self.close_socket(listener)
+    }
+}
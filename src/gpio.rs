@@ -1,6 +1,7 @@
 //! Atwinc1500 gpio related members
 
 /// Gpio pin definitions
+#[derive(Copy, Clone, Debug)]
 pub enum AtwincGpio {
     /// Gpio pin 3
     Gpio3 = 3,
@@ -12,7 +13,21 @@ pub enum AtwincGpio {
     Gpio6 = 6,
 }
 
-#[derive(Eq, PartialEq)]
+impl AtwincGpio {
+    /// Iterates over every gpio pin
+    /// in ascending order
+    pub fn all() -> impl Iterator<Item = AtwincGpio> {
+        [
+            AtwincGpio::Gpio3,
+            AtwincGpio::Gpio4,
+            AtwincGpio::Gpio5,
+            AtwincGpio::Gpio6,
+        ]
+        .into_iter()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 /// Gpio pin directions
 pub enum GpioDirection {
     /// Input pin
@@ -22,16 +37,29 @@ pub enum GpioDirection {
 }
 
 impl From<u8> for GpioDirection {
+    /// Converts a direction register bit,
+    /// any value other than 0 is an input
     fn from(val: u8) -> Self {
         match val {
-            1 => GpioDirection::Input,
             0 => GpioDirection::Output,
-            _ => todo!(),
+            _ => GpioDirection::Input,
         }
     }
 }
 
-#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// Gpio pin pull configuration. The Atwinc1500
+/// only has pull ups and they can only be
+/// controlled on Gpio3, Gpio4 and Gpio5.
+/// Open drain outputs are not supported
+pub enum GpioPull {
+    /// No pull resistor
+    None,
+    /// Pull up resistor enabled
+    Up,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 /// Gpio pin values
 pub enum GpioValue {
     /// Low logic level
@@ -39,3 +67,31 @@ pub enum GpioValue {
     /// High logic level
     High,
 }
+
+/// Directions and levels of every
+/// gpio pin read at the same time
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct GpioSnapshot {
+    /// Direction register, a set bit is an output
+    pub directions: u32,
+    /// Level register, a set bit is high
+    pub values: u32,
+}
+
+impl GpioSnapshot {
+    /// Gets the direction of a gpio pin
+    pub fn direction(&self, gpio: AtwincGpio) -> GpioDirection {
+        match (self.directions >> gpio as u8) & 0x01 {
+            1 => GpioDirection::Output,
+            _ => GpioDirection::Input,
+        }
+    }
+
+    /// Gets the level of a gpio pin
+    pub fn value(&self, gpio: AtwincGpio) -> GpioValue {
+        match (self.values >> gpio as u8) & 0x01 {
+            1 => GpioValue::High,
+            _ => GpioValue::Low,
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use crate::error::Error;
+use crate::dns;
+use crate::error::{Error, HifError};
+use crate::event::Event;
 use crate::registers;
+use crate::socket::{self, SocketCommand, SocketState};
 use crate::spi::SpiBus;
+use crate::state::State;
+use crate::types::{IpConfig, SendInfo, IP_CONFIG_SIZE, RANDOM_BUFFER_SIZE};
+use crate::wifi::{
+    ConnectionInfo, ScanResult, StateChange, Status, CONN_INFO_SIZE, SCAN_RESULT_SIZE,
+};
+use core::net::{Ipv4Addr, SocketAddrV4};
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::OutputPin;
 
 pub mod group_ids {
     pub const _MAIN: u8 = 0;
     pub const WIFI: u8 = 1;
-    pub const _IP: u8 = 2;
+    pub const IP: u8 = 2;
     pub const _HIF: u8 = 3;
 }
 
@@ -19,53 +28,67 @@ pub mod commands {
         pub const REQ_DEFAULT_CONNECT: u8 = 41;
         pub const _RESP_CONNECT: u8 = 42;
         pub const REQ_DISCONNECT: u8 = 43;
-        pub const _RESP_CON_STATE_CHANGED: u8 = 44;
+        pub const RESP_CON_STATE_CHANGED: u8 = 44;
         pub const _REQ_SLEEP: u8 = 45;
+        #[cfg(feature = "wps")]
         pub const _REQ_WPS_SCAN: u8 = 46;
+        #[cfg(feature = "wps")]
         pub const _REQ_WPS: u8 = 47;
+        #[cfg(feature = "wps")]
         pub const _REQ_DISABLE_WPS: u8 = 49;
-        pub const _REQ_DHCP_CONF: u8 = 50;
+        pub const REQ_DHCP_CONF: u8 = 50;
         pub const _RESP_IP_CONFIGURED: u8 = 51;
         pub const _RESP_IP_CONFLICT: u8 = 52;
+        #[cfg(feature = "monitor")]
         pub const _REQ_ENABLE_MONITORING: u8 = 53;
+        #[cfg(feature = "monitor")]
         pub const _REQ_DISABLE_MONITORING: u8 = 54;
+        #[cfg(feature = "monitor")]
         pub const _RESP_WIFI_RX_PACKET: u8 = 55;
+        #[cfg(feature = "monitor")]
         pub const _REQ_SEND_WIFI_PACKET: u8 = 56;
         pub const _REQ_LSN_INT: u8 = 57;
         pub const _REQ_DOZE: u8 = 58;
+        // 19.6.1 and later
+        pub const REQ_CONN: u8 = 59;
+        pub const _IND_CONN_PARAM: u8 = 60;
+        pub const REQ_DELETE_APID: u8 = 61;
 
         // configuration commands
         pub const _REQ_RESTART: u8 = 1;
         pub const _REQ_SET_MAC_ADDRESS: u8 = 2;
-        pub const _REQ_CURRENT_RSSI: u8 = 3;
-        pub const _RESP_CURRENT_RSSI: u8 = 4;
-        pub const _REQ_GET_CONN_INFO: u8 = 5;
-        pub const _RESP_CONN_INFO: u8 = 6;
+        pub const REQ_CURRENT_RSSI: u8 = 3;
+        pub const RESP_CURRENT_RSSI: u8 = 4;
+        pub const REQ_GET_CONN_INFO: u8 = 5;
+        pub const RESP_CONN_INFO: u8 = 6;
         pub const _REQ_SET_DEVICE_NAME: u8 = 7;
+        #[cfg(feature = "provisioning")]
         pub const _REQ_START_PROVISION_MODE: u8 = 8;
+        #[cfg(feature = "provisioning")]
         pub const _RESP_PROVISION_INFO: u8 = 9;
+        #[cfg(feature = "provisioning")]
         pub const _REQ_STOP_PROVISION_MODE: u8 = 10;
         pub const _REQ_SET_SYS_TIME: u8 = 11;
         pub const _REQ_ENABLE_SNTP_CLIENT: u8 = 12;
         pub const _REQ_DISABLE_SNTP_CLIENT: u8 = 13;
         pub const _REQ_CUST_INFO_ELEMENT: u8 = 15;
-        pub const _REQ_SCAN: u8 = 16;
-        pub const _RESP_SCAN_DONE: u8 = 17;
-        pub const _REQ_SCAN_RESULT: u8 = 18;
-        pub const _RESP_SCAN_RESULT: u8 = 19;
-        pub const _REQ_SET_SCAN_OPTION: u8 = 20;
-        pub const _REQ_SET_SCAN_REGION: u8 = 21;
+        pub const REQ_SCAN: u8 = 16;
+        pub const RESP_SCAN_DONE: u8 = 17;
+        pub const REQ_SCAN_RESULT: u8 = 18;
+        pub const RESP_SCAN_RESULT: u8 = 19;
+        pub const REQ_SET_SCAN_OPTION: u8 = 20;
+        pub const REQ_SET_SCAN_REGION: u8 = 21;
         pub const _REQ_SET_POWER_PROFILE: u8 = 22;
-        pub const _REQ_SET_TX_POWER: u8 = 23;
+        pub const REQ_SET_TX_POWER: u8 = 23;
         pub const _REQ_SET_BATTERY_VOLTAGE: u8 = 24;
         pub const _REQ_SET_ENABLE_LOGS: u8 = 25;
         pub const _REQ_GET_SYS_TIME: u8 = 26;
         pub const _RESP_GET_SYS_TIME: u8 = 27;
         pub const _REQ_SEND_ETHERNET_PACKET: u8 = 28;
         pub const _RESP_ETHERNET_RX_PACKET: u8 = 29;
-        pub const _REQ_SET_MAC_MCAST: u8 = 30;
-        pub const _REQ_GET_PRNG: u8 = 31;
-        pub const _RESP_GET_PRNG: u8 = 32;
+        pub const REQ_SET_MAC_MCAST: u8 = 30;
+        pub const REQ_GET_PRNG: u8 = 31;
+        pub const RESP_GET_PRNG: u8 = 32;
         pub const _REQ_SCAN_SSID_LIST: u8 = 33;
         pub const _REQ_SET_GAINS: u8 = 34;
         pub const _REQ_PASSIVE_SCAN: u8 = 35;
@@ -76,6 +99,16 @@ pub mod commands {
 }
 
 const HIF_HEADER_SIZE: usize = 8;
+// Set in the opcode of requests that carry a
+// data payload. It is only written to the
+// register, the header keeps the plain opcode
+pub const REQ_DATA_PKT: u8 = 0x80;
+// Largest packet the Atwinc1500 will
+// allocate a buffer for, header included
+const HIF_MAX_PACKET_SIZE: usize = 1596;
+// Only the first 4 bytes of a received
+// header are meaningful (gid, op, length)
+const HIF_RECEIVE_HEADER_SIZE: usize = 4;
 
 #[derive(Copy, Clone)]
 pub struct HifHeader {
@@ -99,6 +132,12 @@ impl HifHeader {
 impl From<HifHeader> for [u8; HIF_HEADER_SIZE] {
     /// Converts an HifHeader into an array to be sent
     /// to the Atwinc1500
+    ///
+    /// Bytes 4 to 7 are padding that keeps the payload
+    /// word aligned, the firmware never reads them. A
+    /// request carrying a data payload is marked with
+    /// REQ_DATA_PKT in the opcode written to
+    /// NMI_STATE_REG instead, which send_at does
     fn from(header: HifHeader) -> [u8; HIF_HEADER_SIZE] {
         [
             header.gid,
@@ -126,24 +165,60 @@ impl From<HifHeader> for u32 {
     }
 }
 
-impl From<[u8; 4]> for HifHeader {
+impl From<[u8; HIF_RECEIVE_HEADER_SIZE]> for HifHeader {
     /// Converts an array received from the Atwinc1500
-    /// into an HifHeader
-    fn from(array: [u8; 4]) -> Self {
+    /// into an HifHeader. The length is little endian
+    fn from(array: [u8; HIF_RECEIVE_HEADER_SIZE]) -> Self {
         HifHeader {
             gid: array[0],
             op: array[1],
-            length: ((array[2] as u16) << 8) | array[3] as u16,
+            length: ((array[3] as u16) << 8) | array[2] as u16,
         }
     }
 }
 
-/// Empty struct used to represent the Host Interface layer.
+/// Tracks the frame currently being received
+/// from the Atwinc1500
+#[derive(Default)]
+pub struct HifContext {
+    /// Address of the frame on the Atwinc1500
+    pub rx_address: u32,
+    /// Size of the frame
+    pub rx_size: u32,
+    /// Offset from rx_address of the
+    /// next byte receive will read
+    pub rx_offset: u32,
+    /// True while the Atwinc1500 is waiting
+    /// for the host to finish reception
+    pub rx_pending: bool,
+}
+
+/// Struct used to represent the Host Interface layer.
 /// The host interface layer abstracts away all the low level
 /// calls to the spi bus and provides a higher level api to work with.
-pub struct HostInterface;
+pub struct HostInterface {
+    ctx: HifContext,
+    allocation_retries: u32,
+    last_send: Option<SendInfo>,
+}
 
 impl HostInterface {
+    /// Creates a new HostInterface that polls
+    /// allocation_retries times for the Atwinc1500
+    /// to allocate a buffer for a packet
+    pub fn new(allocation_retries: u32) -> Self {
+        Self {
+            ctx: HifContext::default(),
+            allocation_retries,
+            last_send: None,
+        }
+    }
+
+    /// Gets the result of the last successful send
+    pub fn last_send(&self) -> Option<SendInfo> {
+        self.last_send
+    }
+
     /// This method wakes the chip from sleep mode using clockless register access
     pub fn _chip_wake<SPI, O>(&mut self, spi_bus: &mut SpiBus<SPI, O>) -> Result<(), Error>
     where
@@ -172,8 +247,7 @@ impl HostInterface {
             // sleep here?
             trials += 1;
             if trials > _WAKEUP_TRIALS_TIMEOUT as u32 {
-                // error waking chip
-                break;
+                return Err(Error::WakeTimeout);
             }
         }
         Ok(())
@@ -212,94 +286,207 @@ impl HostInterface {
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        todo!()
+        Err(Error::NotImplemented)
     }
 
-    /// This method is the host interface interrupt service routine
-    pub fn _isr<SPI, O>(&mut self, spi_bus: &mut SpiBus<SPI, O>) -> Result<(), Error>
+    /// This method is the host interface interrupt service routine.
+    /// Frames are handled until the Atwinc1500 has none pending
+    pub fn isr<SPI, O>(
+        &mut self,
+        spi_bus: &mut SpiBus<SPI, O>,
+        state: &mut State,
+    ) -> Result<(), Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        let mut reg_value = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_0)?;
-        if reg_value & 0x1 != 0 {
+        loop {
+            let mut reg_value = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_0)?;
+            if reg_value & 0x1 == 0 {
+                break;
+            }
             reg_value &= !0x00000001;
             spi_bus.write_register(registers::WIFI_HOST_RCV_CTRL_0, reg_value)?;
             let size: u16 = ((reg_value >> 2) & 0xfff) as u16;
             if size > 0 {
                 let address: u32 = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_1)?;
-                let mut header_buf: [u8; 4] = [0; 4];
-                spi_bus.read_data(&mut header_buf, address, HIF_HEADER_SIZE as u32)?;
+                // Callbacks read the frame after the header
+                self.ctx = HifContext {
+                    rx_address: address,
+                    rx_size: size as u32,
+                    rx_offset: HIF_HEADER_SIZE as u32,
+                    rx_pending: true,
+                };
+                let mut header_buf: [u8; HIF_RECEIVE_HEADER_SIZE] = [0; HIF_RECEIVE_HEADER_SIZE];
+                spi_bus.read_data(&mut header_buf, address, HIF_RECEIVE_HEADER_SIZE as u32)?;
                 let header = HifHeader::from(header_buf);
-                match header.gid {
-                    group_ids::WIFI => self._wifi_callback(
-                        spi_bus,
-                        header.op,
-                        header.length - HIF_HEADER_SIZE as u16,
-                        address + HIF_HEADER_SIZE as u32,
-                    )?,
-                    group_ids::_IP => self._ip_callback(
-                        spi_bus,
-                        header.op,
-                        header.length - HIF_HEADER_SIZE as u16,
-                        address + HIF_HEADER_SIZE as u32,
-                    )?,
-                    _ => { /* Invalid group id */ }
+                // A header read short or corrupted on the bus
+                // is dropped so its length isn't trusted
+                let known_gid = matches!(header.gid, group_ids::WIFI | group_ids::IP);
+                let data_length = match header.length.checked_sub(HIF_HEADER_SIZE as u16) {
+                    Some(length) if known_gid && header.length <= size => length,
+                    _ => {
+                        self.finish_reception(spi_bus)?;
+                        return Err(HifError::InvalidHeader.into());
+                    }
+                };
+                let result = match header.gid {
+                    group_ids::WIFI => self.wifi_callback(spi_bus, state, header.op, data_length),
+                    group_ids::IP => self.ip_callback(spi_bus, state, header.op, data_length),
+                    _ => Ok(()),
+                };
+                // The callback didn't read to the end of the frame,
+                // including when it rejected the frame part way
+                if self.ctx.rx_pending {
+                    self.finish_reception(spi_bus)?;
                 }
+                result?;
             }
         }
         Ok(())
     }
 
-    /// This method receives data read from the chip
-    pub fn _receive<SPI, O>(
+    /// This method receives data read from the chip.
+    /// Each call reads the bytes following the last
+    /// call, starting after the hif header, so the
+    /// address is always derived from the frame base.
+    /// Reception is finished once the end of the
+    /// frame has been read. Reading past the end of
+    /// the frame returns HifError::AddressMismatch
+    pub fn receive<SPI, O>(
         &mut self,
         spi_bus: &mut SpiBus<SPI, O>,
-        address: u32,
         buffer: &mut [u8],
     ) -> Result<(), Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
+        // The region read must lie inside the frame being received
+        let end: u32 = match self.ctx.rx_offset.checked_add(buffer.len() as u32) {
+            Some(end) if self.ctx.rx_pending && end <= self.ctx.rx_size => end,
+            _ => return Err(HifError::AddressMismatch.into()),
+        };
+        let address: u32 = self.ctx.rx_address + self.ctx.rx_offset;
         spi_bus.read_data(buffer, address, buffer.len() as u32)?;
+        self.ctx.rx_offset = end;
+        if end == self.ctx.rx_size {
+            self.finish_reception(spi_bus)?;
+        }
         Ok(())
     }
 
+    /// Skips ahead so the next receive reads from
+    /// offset bytes after the hif header. Seeking
+    /// backwards or past the end of the frame
+    /// returns HifError::AddressMismatch
+    pub fn seek(&mut self, offset: u32) -> Result<(), Error> {
+        match offset.checked_add(HIF_HEADER_SIZE as u32) {
+            Some(target)
+                if self.ctx.rx_pending
+                    && target >= self.ctx.rx_offset
+                    && target <= self.ctx.rx_size =>
+            {
+                self.ctx.rx_offset = target;
+                Ok(())
+            }
+            _ => Err(HifError::AddressMismatch.into()),
+        }
+    }
+
     /// Lets the atwinc1500 know we're done receiving data
-    fn _finish_reception<SPI, O>(&mut self, spi_bus: &mut SpiBus<SPI, O>) -> Result<(), Error>
+    fn finish_reception<SPI, O>(&mut self, spi_bus: &mut SpiBus<SPI, O>) -> Result<(), Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
         let value: u32 = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_0)?;
         spi_bus.write_register(registers::WIFI_HOST_RCV_CTRL_0, value | 2)?;
+        self.ctx.rx_pending = false;
         Ok(())
     }
 
-    /// This method sends data to the chip
+    /// Recovers from a reception that was never
+    /// finished, for example if the isr was interrupted
+    /// or returned an error part way through a frame.
+    /// Reception is finished unconditionally so the
+    /// Atwinc1500 stops waiting on the host, and the
+    /// context is cleared. A frame the Atwinc1500 has
+    /// signaled since then is left for the next isr
+    pub fn recover<SPI, O>(&mut self, spi_bus: &mut SpiBus<SPI, O>) -> Result<(), Error>
+    where
+        SPI: Transfer<u8>,
+        O: OutputPin,
+    {
+        self.finish_reception(spi_bus)?;
+        self.ctx = HifContext::default();
+        Ok(())
+    }
+
+    /// This method sends data to the chip and
+    /// returns where it was written
     pub fn send<SPI, O>(
         &mut self,
         spi_bus: &mut SpiBus<SPI, O>,
         header: HifHeader,
         data_buffer: &mut [u8],
         ctrl_buffer: &mut [u8],
-    ) -> Result<(), Error>
+    ) -> Result<SendInfo, Error>
+    where
+        SPI: Transfer<u8>,
+        O: OutputPin,
+    {
+        let offset = data_buffer.len();
+        self.send_at(spi_bus, header, data_buffer, ctrl_buffer, offset)
+    }
+
+    /// Like send, but ctrl_buffer is written offset
+    /// bytes after the hif header instead of straight
+    /// after data_buffer. Socket sends need their
+    /// payload at a fixed offset
+    pub fn send_at<SPI, O>(
+        &mut self,
+        spi_bus: &mut SpiBus<SPI, O>,
+        header: HifHeader,
+        data_buffer: &mut [u8],
+        ctrl_buffer: &mut [u8],
+        offset: usize,
+    ) -> Result<SendInfo, Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        let offset: u32 = data_buffer.len() as u32;
+        if offset < data_buffer.len() {
+            return Err(HifError::SizeMismatch.into());
+        }
+        // The Atwinc1500 allocates a buffer of header.length
+        // bytes, so it must cover everything that is written
+        let size: usize = HIF_HEADER_SIZE + offset + ctrl_buffer.len();
+        if size > HIF_MAX_PACKET_SIZE {
+            return Err(HifError::PacketTooLarge.into());
+        }
+        if header.length as usize != size {
+            return Err(HifError::SizeMismatch.into());
+        }
         let mut header_buf: [u8; HIF_HEADER_SIZE] = header.into();
+        header_buf[1] &= !REQ_DATA_PKT;
         let hif: u32 = header.into();
         spi_bus.write_register(registers::NMI_STATE_REG, hif)?;
         spi_bus.write_register(registers::WIFI_HOST_RCV_CTRL_2, 2)?;
         let mut reg_value = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_2)?;
-        retry_while!(reg_value & 2 != 0, retries = 100, {
+        let mut allocation_polls: u32 = 0;
+        retry_while!(reg_value & 2 != 0, retries = self.allocation_retries, {
             reg_value = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_2)?;
+            allocation_polls += 1;
             // may need a delay here
         });
+        if reg_value & 2 != 0 {
+            return Err(HifError::AllocationFailed.into());
+        }
         let address: u32 = spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_4)?;
+        if address == 0 {
+            return Err(HifError::AllocationFailed.into());
+        }
         spi_bus.write_data(&mut header_buf, address, HIF_HEADER_SIZE as u32)?;
         if !data_buffer.is_empty() {
             spi_bus.write_data(
@@ -311,12 +498,18 @@ impl HostInterface {
         if !ctrl_buffer.is_empty() {
             spi_bus.write_data(
                 ctrl_buffer,
-                address + HIF_HEADER_SIZE as u32 + offset,
+                address + (HIF_HEADER_SIZE + offset) as u32,
                 ctrl_buffer.len() as u32,
             )?;
         }
         spi_bus.write_register(registers::WIFI_HOST_RCV_CTRL_3, (address << 2) | 2)?;
-        Ok(())
+        let info = SendInfo {
+            address,
+            length: header.length,
+            allocation_polls,
+        };
+        self.last_send = Some(info);
+        Ok(info)
     }
 
     /// This method sets the chip sleep mode
@@ -325,7 +518,7 @@ impl HostInterface {
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        todo!()
+        Err(Error::NotImplemented)
     }
 
     /// This method returns the chip sleep mode
@@ -334,46 +527,295 @@ impl HostInterface {
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        todo!()
+        Err(Error::NotImplemented)
     }
 
-    pub fn _wifi_callback<SPI, O>(
+    pub fn wifi_callback<SPI, O>(
         &mut self,
-        _spi_bus: &mut SpiBus<SPI, O>,
+        spi_bus: &mut SpiBus<SPI, O>,
+        state: &mut State,
         opcode: u8,
         _data_size: u16,
-        _address: u32,
     ) -> Result<(), Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
         match opcode {
-            commands::wifi::_RESP_CON_STATE_CHANGED => {}
+            commands::wifi::RESP_CON_STATE_CHANGED => {
+                let mut data: [u8; 4] = [0; 4];
+                self.receive(spi_bus, &mut data)?;
+                let change = StateChange::try_from(&data[..])?;
+                // A timed out attempt is abandoned with a
+                // disconnect, which the firmware confirms. That
+                // confirmation must not fail the next attempt
+                let pending = core::mem::take(&mut state.disconnect_pending);
+                if pending && !change.connected {
+                    return Ok(());
+                }
+                if change.connected {
+                    state.connected_at_ms = Some(state.now_ms);
+                } else {
+                    if state.status == Status::Connected {
+                        state.reconnect_count = state.reconnect_count.saturating_add(1);
+                        state.last_disconnect_ms = Some(state.now_ms);
+                    }
+                    state.connected_at_ms = None;
+                    state.conn_info = None;
+                    state.last_disconnect_reason = Some(change.error_code);
+                }
+                state.status = if change.connected {
+                    Status::Connected
+                } else if state.status == Status::Connecting {
+                    Status::ConnectionFailed
+                } else {
+                    Status::Disconnected
+                };
+                if !change.connected && state.ip_config.take().is_some() {
+                    state.events.push(Event::DhcpLost);
+                }
+            }
             commands::wifi::_RESP_GET_SYS_TIME => {}
-            commands::wifi::_RESP_CONN_INFO => {}
-            commands::wifi::_REQ_DHCP_CONF => {}
+            commands::wifi::RESP_GET_PRNG => {
+                // buffer pointer (4 bytes), size (2 bytes),
+                // padding (2 bytes) followed by the bytes
+                let mut data: [u8; 8] = [0; 8];
+                self.receive(spi_bus, &mut data)?;
+                let size = u16::from_le_bytes([data[4], data[5]]) as usize;
+                let size = size.min(RANDOM_BUFFER_SIZE);
+                self.receive(spi_bus, &mut state.random_bytes[..size])?;
+                state.random_len = Some(size);
+                state.events.push(Event::RandomBytes);
+            }
+            commands::wifi::RESP_CONN_INFO => {
+                let mut data: [u8; CONN_INFO_SIZE] = [0; CONN_INFO_SIZE];
+                self.receive(spi_bus, &mut data)?;
+                let info = ConnectionInfo::from(data);
+                state.rssi = Some(info.rssi);
+                state.conn_info = Some(info);
+                state.events.push(Event::ConnectionInfo);
+            }
+            commands::wifi::REQ_DHCP_CONF => {
+                let mut data: [u8; IP_CONFIG_SIZE] = [0; IP_CONFIG_SIZE];
+                self.receive(spi_bus, &mut data)?;
+                // The firmware sends the configuration again
+                // each time the lease is renewed
+                let event = match state.ip_config {
+                    Some(_) => Event::DhcpRenewed,
+                    None => Event::IpConfigured,
+                };
+                state.ip_config = Some(IpConfig::from(data));
+                state.events.push(event);
+            }
+            #[cfg(feature = "wps")]
             commands::wifi::_REQ_WPS => {}
             commands::wifi::_RESP_IP_CONFLICT => {}
-            commands::wifi::_RESP_SCAN_DONE => {}
-            commands::wifi::_RESP_SCAN_RESULT => {}
-            commands::wifi::_RESP_CURRENT_RSSI => {}
+            commands::wifi::RESP_SCAN_DONE => {
+                let mut data: [u8; 4] = [0; 4];
+                self.receive(spi_bus, &mut data)?;
+                state.num_ap = data[0];
+                state.scan_in_progress = false;
+                state.events.push(Event::ScanDone { num_ap: data[0] });
+            }
+            commands::wifi::RESP_SCAN_RESULT => {
+                let mut data: [u8; SCAN_RESULT_SIZE] = [0; SCAN_RESULT_SIZE];
+                self.receive(spi_bus, &mut data)?;
+                let result = ScanResult::try_from(&data[..])?;
+                state.events.push(Event::ScanResult {
+                    index: result.index,
+                });
+                if let Some(slot) = state.scan_results.get_mut(result.index as usize) {
+                    *slot = Some(result);
+                }
+                state.scan_result = Some(result);
+            }
+            commands::wifi::RESP_CURRENT_RSSI => {
+                // rssi, padding (3 bytes)
+                let mut data: [u8; 4] = [0; 4];
+                self.receive(spi_bus, &mut data)?;
+                state.rssi = Some(data[0] as i8);
+            }
             _ => {}
         }
         Ok(())
     }
 
-    pub fn _ip_callback<SPI, O>(
+    pub fn ip_callback<SPI, O>(
         &mut self,
-        _spi_bus: &mut SpiBus<SPI, O>,
-        _opcode: u8,
+        spi_bus: &mut SpiBus<SPI, O>,
+        state: &mut State,
+        opcode: u8,
         _data_size: u16,
-        _address: u32,
     ) -> Result<(), Error>
     where
         SPI: Transfer<u8>,
         O: OutputPin,
     {
-        todo!()
+        let command = match SocketCommand::try_from(opcode) {
+            Ok(command) if command.is_response() => command,
+            _ => return Ok(()),
+        };
+        match command {
+            SocketCommand::Accept => {
+                // address (8 bytes), listen socket, connected
+                // socket, data offset (2 bytes)
+                let mut data: [u8; 12] = [0; 12];
+                self.receive(spi_bus, &mut data)?;
+                let handle = socket_handle(data[8])?;
+                let connected = data[9] as usize;
+                // The firmware picks the handle of the connection,
+                // which shares the session of the listening socket
+                let session = match state.sockets.get(handle as usize) {
+                    Some(Some(listener)) => Some(listener.session),
+                    _ => None,
+                };
+                if let (Some(session), true) = (session, connected < socket::TCP_SOCK_MAX) {
+                    let mut accepted = SocketState::new(session);
+                    accepted.accepted_by = Some(handle);
+                    accepted.peer = Some(SocketAddrV4::new(
+                        Ipv4Addr::new(data[4], data[5], data[6], data[7]),
+                        u16::from_be_bytes([data[2], data[3]]),
+                    ));
+                    state.sockets[connected] = Some(accepted);
+                    state.socket_readiness.clear(connected as u8);
+                }
+                state.push_socket_event(Event::SocketReadable { handle });
+            }
+            SocketCommand::Bind | SocketCommand::Listen => {
+                // socket, status, session (2 bytes)
+                let mut data: [u8; 4] = [0; 4];
+                self.receive(spi_bus, &mut data)?;
+                let handle = socket_handle(data[0])?;
+                if (data[1] as i8) < 0 {
+                    state.push_socket_event(Event::SocketClosed { handle });
+                }
+            }
+            SocketCommand::Connect | SocketCommand::SslConnect => {
+                // socket, error, data offset (2 bytes)
+                let mut data: [u8; 4] = [0; 4];
+                self.receive(spi_bus, &mut data)?;
+                let handle = socket_handle(data[0])?;
+                let failed = (data[1] as i8) < 0;
+                if let Some(Some(socket)) = state.sockets.get_mut(handle as usize) {
+                    socket.connecting = false;
+                    socket.connected = !failed;
+                    socket.handshake_failed = failed && command == SocketCommand::SslConnect;
+                    // The data offset counts from the start of
+                    // the hif header, sends count from its end
+                    let offset = u16::from_le_bytes([data[2], data[3]]) as usize;
+                    if !failed && offset > HIF_HEADER_SIZE {
+                        socket.tx_offset = offset - HIF_HEADER_SIZE;
+                    }
+                }
+                let event = match failed {
+                    true => Event::SocketClosed { handle },
+                    false => Event::SocketWritable { handle },
+                };
+                state.push_socket_event(event);
+            }
+            SocketCommand::Recv | SocketCommand::RecvFrom | SocketCommand::SslRecv => {
+                // address (8 bytes), status (2 bytes), data
+                // offset (2 bytes), socket, padding, session (2 bytes)
+                let mut data: [u8; 16] = [0; 16];
+                self.receive(spi_bus, &mut data)?;
+                let handle = socket_handle(data[12])?;
+                let status = i16::from_le_bytes([data[8], data[9]]);
+                let offset = u16::from_le_bytes([data[10], data[11]]);
+                let session = u16::from_le_bytes([data[14], data[15]]);
+                // A reply for a socket closed since the request
+                // was sent is dropped, its handle may already
+                // belong to a new socket
+                let socket = match state
+                    .sockets
+                    .get_mut(handle as usize)
+                    .and_then(|s| s.as_mut())
+                    .filter(|s| s.session == session)
+                {
+                    Some(socket) => socket,
+                    None => return Ok(()),
+                };
+                socket.recv_pending = false;
+                if status > 0 {
+                    self.seek(offset as u32)?;
+                    let mut remaining = status as usize;
+                    let (first, second) = socket.rx.free_slices();
+                    for slice in [first, second] {
+                        let count = remaining.min(slice.len());
+                        if count > 0 {
+                            self.receive(spi_bus, &mut slice[..count])?;
+                        }
+                        remaining -= count;
+                    }
+                    socket.rx.commit(status as usize - remaining);
+                    socket.overflow |= remaining > 0;
+                }
+                match status {
+                    status if status > 0 => {
+                        state.push_socket_event(Event::SocketReadable { handle })
+                    }
+                    // A receive timeout leaves the socket open
+                    socket::SOCK_ERR_TIMEOUT => {}
+                    _ => state.push_socket_event(Event::SocketClosed { handle }),
+                }
+            }
+            SocketCommand::Send | SocketCommand::SendTo | SocketCommand::SslSend => {
+                // socket, padding, bytes sent (2 bytes),
+                // session (2 bytes), padding (2 bytes)
+                let mut data: [u8; 8] = [0; 8];
+                self.receive(spi_bus, &mut data)?;
+                let handle = socket_handle(data[0])?;
+                let session = u16::from_le_bytes([data[4], data[5]]);
+                // Dropped like a stale receive reply
+                if !state.is_current_session(handle, session) {
+                    return Ok(());
+                }
+                let event = match i16::from_le_bytes([data[2], data[3]]) {
+                    sent if sent < 0 => Event::SocketClosed { handle },
+                    _ => Event::SocketWritable { handle },
+                };
+                state.push_socket_event(event);
+            }
+            // The firmware runs one dns resolve
+            // and one ping at a time
+            SocketCommand::DnsResolve => {
+                // host name (64 bytes), ip address
+                let mut data: [u8; dns::DNS_REPLY_SIZE] = [0; dns::DNS_REPLY_SIZE];
+                self.receive(spi_bus, &mut data)?;
+                let (host, ip) = data.split_at(dns::HOSTNAME_MAX_SIZE);
+                let len = host.iter().position(|b| *b == 0).unwrap_or(host.len());
+                let ip = Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]);
+                // An address of 0 means the host wasn't found
+                if !ip.is_unspecified() {
+                    state.dns_cache.insert(&host[..len], ip, state.now_ms);
+                }
+                state.dns_result = Some(ip);
+                state.dns_in_progress = false;
+                state.events.push(Event::DnsResolved);
+            }
+            SocketCommand::Ping => {
+                // ip address, private (4 bytes), round trip time
+                // (4 bytes), replies (2 bytes), failures (2 bytes),
+                // error code, padding (3 bytes)
+                let mut data: [u8; socket::PING_REPLY_SIZE] = [0; socket::PING_REPLY_SIZE];
+                self.receive(spi_bus, &mut data)?;
+                state.ping_result = Some(socket::PingResult {
+                    rtt_ms: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+                    error: data[16],
+                });
+                state.ping_in_progress = false;
+                state.events.push(Event::PingDone);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Checks a socket handle sent by the Atwinc1500
+/// is one the firmware can have
+fn socket_handle(handle: u8) -> Result<u8, Error> {
+    if handle as usize >= socket::MAX_SOCKETS {
+        return Err(HifError::InvalidFrame.into());
     }
+    Ok(handle)
 }
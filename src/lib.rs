@@ -1,32 +1,53 @@
 #![doc = include_str!("../README.md")]
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 
 #[macro_use]
 mod macros;
 mod crc;
+pub mod dns;
 pub mod error;
+pub mod event;
+#[cfg(feature = "examples")]
+mod examples;
 pub mod gpio;
 mod hif;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 #[doc(hidden)]
 pub mod registers;
 pub mod socket;
 #[doc(hidden)]
 pub mod spi;
+mod state;
 pub mod types;
+
+#[cfg(feature = "test-internals")]
+pub use state::{DebugSocket, DebugState};
 pub mod wifi;
 
+use core::net::{Ipv4Addr, SocketAddrV4};
 use embedded_hal::blocking::{delay::DelayMs, spi::Transfer};
 use embedded_hal::digital::v2::{InputPin, OutputPin};
-use embedded_nal::{SocketAddr, TcpClientStack, TcpFullStack};
+#[cfg(feature = "nal")]
+use embedded_nal::{AddrType, Dns, IpAddr, SocketAddr, TcpClientStack, TcpFullStack};
 
-use error::Error;
-use gpio::{AtwincGpio, GpioDirection, GpioValue};
-use hif::{commands, group_ids, HifHeader, HostInterface};
-use socket::TcpSocket;
+use error::{Error, HifError, SpiErrorContext};
+use event::Event;
+use gpio::{AtwincGpio, GpioDirection, GpioPull, GpioSnapshot, GpioValue};
+use hif::{commands, group_ids, HifHeader, HostInterface, REQ_DATA_PKT};
+use socket::{CipherSuite, PingResult, SocketCommand, SocketReadiness, SocketState, TcpSocket};
 use spi::SpiBus;
-use types::{FirmwareVersion, MacAddress};
-use wifi::{ConnectionParameters, OldConnection};
+use state::State;
+use types::{
+    ChipSelect, Features, FirmwareVersion, FlashInfo, InitConfig, IpConfig, MacAddress, Reset,
+    SendInfo, Wake, RANDOM_BUFFER_SIZE,
+};
+use wifi::{
+    Channel, ConnectionInfo, ConnectionParameters, LinkHealth, NewConnection, OldConnection,
+    ScanCount, ScanOptions, ScanRegion, ScanResult, StateChangeErrorCode, Status, TxPower,
+    MAX_MULTICAST_FILTERS, MAX_SCAN_RESULTS,
+};
 
 /// Atwin1500 driver struct
 pub struct Atwinc1500<SPI, D, O, I>
@@ -43,6 +64,8 @@ where
     reset: O,
     wake: O,
     crc: bool,
+    config: InitConfig,
+    state: State,
 }
 
 /// Atwinc1500 struct implementation containing non embedded-nal
@@ -72,77 +95,148 @@ where
     ///
     /// * `crc` - Turn on CRC in transactions
     ///
+    /// The output pins share a type, so they are wrapped in
+    /// ChipSelect, Reset and Wake to keep them from being
+    /// passed in the wrong order. The driver can't tell if
+    /// two of them are the same physical pin. If initialization
+    /// fails with Error::CrcDisableError or the bootrom never
+    /// becomes ready, check the chip select and reset wiring
+    ///
     pub fn new(
         spi: SPI,
         delay: D,
-        cs: O,
+        cs: ChipSelect<O>,
         _irq: I,
-        reset: O,
-        wake: O,
+        reset: Reset<O>,
+        wake: Wake<O>,
         crc: bool,
     ) -> Result<Self, Error> {
-        let mut s = Self {
+        Self::new_with_config(
+            spi,
             delay,
-            spi_bus: SpiBus::new(spi, cs, crc),
-            hif: HostInterface {},
+            cs,
             _irq,
             reset,
             wake,
             crc,
+            InitConfig::default(),
+        )
+    }
+
+    /// Returns an Atwin1500 struct initialized
+    /// with config instead of the default driver
+    /// version and configuration. This is needed
+    /// for firmware that expects a different
+    /// driver version
+    ///
+    /// # Arguments
+    ///
+    /// See new
+    ///
+    /// * `config` - Values written during initialization
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_config(
+        spi: SPI,
+        delay: D,
+        cs: ChipSelect<O>,
+        _irq: I,
+        reset: Reset<O>,
+        wake: Wake<O>,
+        crc: bool,
+        config: InitConfig,
+    ) -> Result<Self, Error> {
+        let mut s = Self {
+            delay,
+            spi_bus: SpiBus::new(spi, cs.0, crc),
+            hif: HostInterface::new(config.allocation_retries),
+            _irq,
+            reset: reset.0,
+            wake: wake.0,
+            crc,
+            config,
+            state: State::new(),
         };
+        s.spi_bus.set_read_retries(config.read_retries);
+        s.spi_bus.set_write_retries(config.write_retries);
+        s.spi_bus.set_max_transfer(config.max_transfer);
         s.initialize()?;
         Ok(s)
     }
 
     /// Initializes the driver by:
     /// * Initializing pins between devices
+    /// * Booting the Atwinc1500
+    fn initialize(&mut self) -> Result<(), Error> {
+        self.init_pins()?;
+        self.boot()
+    }
+
+    /// Boots the Atwinc1500 after a reset by:
     /// * Disables crc if needed
     /// * Waits for efuse ready
     /// * Waits for boot rom ready
     /// * Writes driver version and configuration
+    /// * Waits for the firmware to finish initializing
     /// * Enables chip interrupt
-    fn initialize(&mut self) -> Result<(), Error> {
+    fn boot(&mut self) -> Result<(), Error> {
         const FINISH_BOOT_VAL: u32 = 0x10add09e;
-        const DRIVER_VER_INFO: u32 = 0x13521330;
-        const CONF_VAL: u32 = 0x102;
         const START_FIRMWARE: u32 = 0xef522f61;
         const FINISH_INIT_VAL: u32 = 0x02532636;
-        self.init_pins()?;
         self.disable_crc()?;
         let mut efuse_value: u32 = 0;
-        retry_while!((efuse_value & 0x80000000) == 0, retries = 10, {
-            efuse_value = self.spi_bus.read_register(registers::EFUSE_REG)?;
-            self.delay.delay_ms(1000);
-        });
+        retry_while!(
+            (efuse_value & 0x80000000) == 0,
+            retries = self.config.efuse_retries,
+            {
+                efuse_value = self.spi_bus.read_register(registers::EFUSE_REG)?;
+                self.delay.delay_ms(1000);
+            }
+        );
         let wait: u32 = self
             .spi_bus
             .read_register(registers::M2M_WAIT_FOR_HOST_REG)?;
         if (wait & 1) == 0 {
             let mut bootrom: u32 = 0;
-            retry_while!(bootrom != FINISH_BOOT_VAL, retries = 3, {
-                bootrom = self.spi_bus.read_register(registers::BOOTROM_REG)?;
-                self.delay.delay_ms(1000);
-            });
+            retry_while!(
+                bootrom != FINISH_BOOT_VAL,
+                retries = self.config.bootrom_retries,
+                {
+                    bootrom = self.spi_bus.read_register(registers::BOOTROM_REG)?;
+                    self.delay.delay_ms(1000);
+                }
+            );
         }
         self.spi_bus
-            .write_register(registers::NMI_STATE_REG, DRIVER_VER_INFO)?;
+            .write_register(registers::NMI_STATE_REG, self.config.version_info)?;
         self.spi_bus
-            .write_register(registers::rNMI_GP_REG_1, CONF_VAL)?;
+            .write_register(registers::rNMI_GP_REG_1, self.config.conf)?;
         self.spi_bus
             .write_register(registers::BOOTROM_REG, START_FIRMWARE)?;
         let mut state: u32 = 0;
-        retry_while!(state != FINISH_INIT_VAL, retries = 20, {
-            state = self.spi_bus.read_register(registers::NMI_STATE_REG)?;
-            self.delay.delay_ms(1000);
-        });
+        retry_while!(
+            state != FINISH_INIT_VAL,
+            retries = self.config.firmware_start_retries,
+            {
+                state = self.get_init_state()?;
+                self.delay.delay_ms(1000);
+            }
+        );
+        if state != FINISH_INIT_VAL {
+            return Err(Error::InitTimeout);
+        }
         self.spi_bus.write_register(registers::NMI_STATE_REG, 0)?;
         self.enable_chip_interrupt()?;
+        self.state.firmware_version = Some(self.get_firmware_version()?);
+        if self.config.driver_version() < self.get_min_driver_version()? {
+            return Err(Error::DriverVersionMismatch);
+        }
         Ok(())
     }
 
     /// Pulls the chip select and wake pins high
     /// Then pulses (low/high) the reset pin with
-    /// a delay
+    /// the delays from the InitConfig
     fn init_pins(&mut self) -> Result<(), Error> {
         self.spi_bus.init_cs()?;
         if self.wake.set_high().is_err() {
@@ -151,24 +245,42 @@ where
         if self.reset.set_low().is_err() {
             return Err(Error::PinStateError);
         }
-        self.delay.delay_ms(1000);
+        self.delay.delay_ms(self.config.reset_low_ms);
         if self.reset.set_high().is_err() {
             return Err(Error::PinStateError);
         }
-        self.delay.delay_ms(1000);
+        self.delay.delay_ms(self.config.reset_high_ms);
         Ok(())
     }
 
-    /// Disables crc if self.crc is false
+    /// Disables crc if self.crc is false then
+    /// reads the protocol config back to make
+    /// sure the Atwinc1500 accepted it
     fn disable_crc(&mut self) -> Result<(), Error> {
+        // Bits 2 and 3 enable crc
+        const CRC_BITS: u32 = 0xc;
         if !self.crc {
             self.spi_bus
                 .write_register(registers::NMI_SPI_PROTOCOL_CONFIG, 0x52)?;
             self.spi_bus.crc_disabled()?;
+            let config = match self.get_spi_protocol_config() {
+                Ok(config) => config,
+                Err(_) => return Err(Error::CrcDisableError),
+            };
+            if config & CRC_BITS != 0 {
+                return Err(Error::CrcDisableError);
+            }
         }
         Ok(())
     }
 
+    /// Gets the spi protocol configuration
+    /// the Atwinc1500 is currently using
+    pub fn get_spi_protocol_config(&mut self) -> Result<u32, Error> {
+        self.spi_bus
+            .read_register(registers::NMI_SPI_PROTOCOL_CONFIG)
+    }
+
     fn enable_chip_interrupt(&mut self) -> Result<(), Error> {
         let mux: u32 = self.spi_bus.read_register(registers::NMI_PIN_MUX_0)?;
         self.spi_bus
@@ -179,13 +291,68 @@ where
         Ok(())
     }
 
+    fn disable_chip_interrupt(&mut self) -> Result<(), Error> {
+        let base: u32 = self.spi_bus.read_register(registers::NMI_INTR_REG_BASE)?;
+        self.spi_bus
+            .write_register(registers::NMI_INTR_REG_BASE, base & !0x10000)?;
+        let mux: u32 = self.spi_bus.read_register(registers::NMI_PIN_MUX_0)?;
+        self.spi_bus
+            .write_register(registers::NMI_PIN_MUX_0, mux & !0x100)?;
+        Ok(())
+    }
+
+    /// Gets the command and address of the last spi
+    /// transaction that failed, for finding which part
+    /// of a larger request like connect_network failed
+    pub fn get_last_spi_error(&self) -> Option<SpiErrorContext> {
+        self.spi_bus.last_error()
+    }
+
+    /// Gets the spi peripheral passed to new, so its
+    /// clock can be changed between phases. The
+    /// Atwinc1500 accepts up to 48MHz, but the bootrom
+    /// and register accesses during initialization are
+    /// the most sensitive to noise on long wires.
+    /// Initialize at a slow clock, raise it once new
+    /// returns, and lower it again before calling
+    /// recover or soft_reset. No transaction is in
+    /// progress between driver calls
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        self.spi_bus.spi_mut()
+    }
+
+    /// Gets where the last packet sent to the
+    /// Atwinc1500 was written and how many polls
+    /// its buffer allocation took
+    pub fn get_last_send_info(&self) -> Option<SendInfo> {
+        self.hif.last_send()
+    }
+
+    /// Gets the raw value of NMI_STATE_REG for
+    /// diagnosing a chip that won't initialize.
+    /// * The driver version info means the
+    ///   firmware never started
+    /// * 0x02532636 means the firmware finished
+    ///   initializing and is waiting on the driver
+    /// * 0 means initialization completed
+    pub fn get_init_state(&mut self) -> Result<u32, Error> {
+        self.spi_bus.read_register(registers::NMI_STATE_REG)
+    }
+
+    /// Gets the features supported by the firmware
+    /// version read during initialization
+    pub fn supported_features(&self) -> Features {
+        Features::from(
+            self.state
+                .firmware_version
+                .unwrap_or(FirmwareVersion([0, 0, 0])),
+        )
+    }
+
     /// Gets the version of the firmware on
     /// the Atwinc1500
     pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error> {
-        let mut reg_value = self.spi_bus.read_register(registers::NMI_REV_REG)?;
-        if reg_value == registers::M2M_ATE_FW_IS_UP_VALUE {
-            reg_value = self.spi_bus.read_register(registers::NMI_REV_REG_ATE)?;
-        }
+        let reg_value = self.read_revision()?;
         Ok(FirmwareVersion([
             ((reg_value >> 8) & 0xff) as u8, // major
             ((reg_value >> 4) & 0x0f) as u8, // minor
@@ -193,10 +360,65 @@ where
         ]))
     }
 
+    /// Gets the oldest driver version the firmware
+    /// on the Atwinc1500 works with. Initialization
+    /// fails with Error::DriverVersionMismatch if the
+    /// driver version in InitConfig is older
+    pub fn get_min_driver_version(&mut self) -> Result<FirmwareVersion, Error> {
+        let reg_value = self.read_revision()?;
+        Ok(FirmwareVersion([
+            ((reg_value >> 24) & 0xff) as u8, // major
+            ((reg_value >> 20) & 0x0f) as u8, // minor
+            ((reg_value >> 16) & 0x0f) as u8, // patch
+        ]))
+    }
+
+    /// Reads the revision register. The high 16 bits
+    /// are the minimum driver version and the low 16
+    /// bits are the firmware version
+    fn read_revision(&mut self) -> Result<u32, Error> {
+        let reg_value = self.spi_bus.read_register(registers::NMI_REV_REG)?;
+        if reg_value == registers::M2M_ATE_FW_IS_UP_VALUE {
+            return self.spi_bus.read_register(registers::NMI_REV_REG_ATE);
+        }
+        Ok(reg_value)
+    }
+
+    /// Reads the jedec id of the spi flash inside the
+    /// Atwinc1500 to get its size. The firmware uses
+    /// the flash while it runs, so stop it with
+    /// halt_cpu first and reinitialize with recover
+    /// afterwards
+    ///
+    /// From spi_flash_get_size in the Atmel driver
+    pub fn get_flash_info(&mut self) -> Result<FlashInfo, Error> {
+        const READ_ID: u32 = 0x9f;
+        self.spi_bus
+            .write_register(registers::SPI_FLASH_DATA_CNT, 4)?;
+        self.spi_bus
+            .write_register(registers::SPI_FLASH_BUF1, READ_ID)?;
+        self.spi_bus
+            .write_register(registers::SPI_FLASH_BUF_DIR, 1)?;
+        self.spi_bus
+            .write_register(registers::SPI_FLASH_DMA_ADDR, registers::DUMMY_REGISTER)?;
+        // One command byte, bit 7 starts the transfer
+        self.spi_bus
+            .write_register(registers::SPI_FLASH_CMD_CNT, 1 | (1 << 7))?;
+        let mut done = self.spi_bus.read_register(registers::SPI_FLASH_TR_DONE)?;
+        retry_while!(done != 1, retries = 500, {
+            done = self.spi_bus.read_register(registers::SPI_FLASH_TR_DONE)?;
+        });
+        if done != 1 {
+            return Err(Error::FlashNotDetected);
+        }
+        let id = self.spi_bus.read_register(registers::DUMMY_REGISTER)?;
+        FlashInfo::try_from(id)
+    }
+
     /// Gets the mac address stored in
     /// one time programmable memory
     pub fn get_otp_mac_address(&mut self) -> Result<MacAddress, Error> {
-        todo!()
+        Err(Error::NotImplemented)
     }
 
     /// Gets the working mac address
@@ -225,27 +447,26 @@ where
         gpio: AtwincGpio,
         direction: GpioDirection,
     ) -> Result<(), Error> {
-        const GPIO_DIR_REG: u32 = 0x20108;
-        let mut value = self.spi_bus.read_register(GPIO_DIR_REG)?;
+        let mut value = self.spi_bus.read_register(registers::GPIO_DIR_REG)?;
         if direction == GpioDirection::Output {
             value |= 1 << gpio as u8;
         } else {
             value &= !(1 << gpio as u8);
         }
-        self.spi_bus.write_register(GPIO_DIR_REG, value)
+        self.spi_bus.write_register(registers::GPIO_DIR_REG, value)
     }
 
     /// Sets the value of a gpio
     /// pin as either High or Low
     pub fn set_gpio_value(&mut self, gpio: AtwincGpio, value: GpioValue) -> Result<(), Error> {
-        const GPIO_VAL_REG: u32 = 0x20100;
-        let mut response = self.spi_bus.read_register(GPIO_VAL_REG)?;
+        let mut response = self.spi_bus.read_register(registers::GPIO_VAL_REG)?;
         if value == GpioValue::Low {
             response |= 1 << gpio as u8;
         } else {
             response &= !(1 << gpio as u8);
         }
-        self.spi_bus.write_register(GPIO_VAL_REG, response)
+        self.spi_bus
+            .write_register(registers::GPIO_VAL_REG, response)
     }
 
     /// Gets the direction of a gpio pin
@@ -258,20 +479,1331 @@ where
         }
     }
 
-    /// Connects to a wireless network
-    /// given a ConnectionParameters struct
-    pub fn connect_network(&mut self, connection: ConnectionParameters) -> Result<(), Error> {
-        let mut conn_header: OldConnection = connection.into();
+    /// Enables or disables the pull up on a
+    /// gpio pin. Only Gpio3, Gpio4 and Gpio5 have
+    /// a pull up that can be controlled, Gpio6
+    /// returns Error::InvalidGpio
+    pub fn set_gpio_pull(&mut self, gpio: AtwincGpio, pull: GpioPull) -> Result<(), Error> {
+        // The pull up disable bits for
+        // gpio 3-5 match their pin number
+        if let AtwincGpio::Gpio6 = gpio {
+            return Err(Error::InvalidGpio);
+        }
+        let mut value = self.spi_bus.read_register(registers::PULLUP_CTRL_REG)?;
+        match pull {
+            GpioPull::Up => value &= !(1 << gpio as u8),
+            GpioPull::None => value |= 1 << gpio as u8,
+        }
+        self.spi_bus
+            .write_register(registers::PULLUP_CTRL_REG, value)
+    }
+
+    /// Reads the direction and level of every
+    /// gpio pin with one read of each register
+    pub fn read_all_gpio(&mut self) -> Result<GpioSnapshot, Error> {
+        let directions = self.spi_bus.read_register(registers::GPIO_DIR_REG)?;
+        let values = self.spi_bus.read_register(registers::GPIO_GET_VAL_REG)?;
+        Ok(GpioSnapshot { directions, values })
+    }
+
+    /// Handles events sent from the Atwinc1500.
+    /// This should be called whenever the irq
+    /// pin is pulled low. Events are queued
+    /// and can be read with next_event. See
+    /// the Interrupts section of the crate docs
+    /// for using this from an interrupt handler
+    pub fn handle_events(&mut self) -> Result<(), Error> {
+        self.hif.isr(&mut self.spi_bus, &mut self.state)
+    }
+
+    /// Recovers the host interface when a frame
+    /// was left partially received, for example if
+    /// handle_events was preempted or returned an
+    /// error. Events sent after this are handled
+    /// normally without resetting the chip
+    pub fn hif_recover(&mut self) -> Result<(), Error> {
+        self.hif.recover(&mut self.spi_bus)
+    }
+
+    /// Masks the chip interrupt so the irq line stays
+    /// quiet during a critical section. The Atwinc1500
+    /// keeps frames it sends in the meantime, and
+    /// handle_events still reads them if called
+    pub fn disable_events(&mut self) -> Result<(), Error> {
+        let base: u32 = self.spi_bus.read_register(registers::NMI_INTR_REG_BASE)?;
+        self.spi_bus
+            .write_register(registers::NMI_INTR_REG_BASE, base & !0x10000)
+    }
+
+    /// Unmasks the chip interrupt after disable_events
+    /// and handles the events that arrived while it
+    /// was masked
+    pub fn enable_events(&mut self) -> Result<(), Error> {
+        let base: u32 = self.spi_bus.read_register(registers::NMI_INTR_REG_BASE)?;
+        self.spi_bus
+            .write_register(registers::NMI_INTR_REG_BASE, base | 0x10000)?;
+        self.handle_events()
+    }
+
+    /// Gets the raw value of WIFI_HOST_RCV_CTRL_0, the
+    /// register the Atwinc1500 uses to signal a frame,
+    /// for debugging an interrupt line that stays asserted
+    /// * Bit 0 is set while a frame is waiting for the host
+    /// * Bit 1 is set by the host when it finished reading
+    /// * Bits 2 through 13 are the size of the frame
+    pub fn get_interrupt_status(&mut self) -> Result<u32, Error> {
+        self.spi_bus.read_register(registers::WIFI_HOST_RCV_CTRL_0)
+    }
+
+    /// Acknowledges the interrupt by clearing bit 0 of
+    /// WIFI_HOST_RCV_CTRL_0, as handle_events does before
+    /// reading a frame. The frame is not read, so the
+    /// Atwinc1500 keeps waiting on the host until
+    /// hif_recover finishes the reception
+    pub fn clear_interrupt(&mut self) -> Result<(), Error> {
+        let reg_value = self.get_interrupt_status()?;
+        self.spi_bus
+            .write_register(registers::WIFI_HOST_RCV_CTRL_0, reg_value & !0x1)
+    }
+
+    /// Resets the Atwinc1500 with the reset pin and
+    /// initializes it again, keeping the spi bus, pins
+    /// and configuration. Connection state, scan results
+    /// and queued events are cleared, so the network has
+    /// to be connected again afterwards
+    pub fn recover(&mut self) -> Result<(), Error> {
+        self.clear_state()?;
+        self.initialize()
+    }
+
+    /// Resets the Atwinc1500 over spi without using
+    /// the reset pin and boots it again. Like recover,
+    /// the network has to be connected again afterwards
+    pub fn soft_reset(&mut self) -> Result<(), Error> {
+        self.chip_reset()?;
+        self.clear_state()?;
+        self.boot()
+    }
+
+    /// Stops the cpu on the Atwinc1500 so the
+    /// firmware no longer runs. The Atwinc1500 has to
+    /// be reset with recover or soft_reset afterwards
+    ///
+    /// From cpu_halt in the Atmel driver
+    pub fn halt_cpu(&mut self) -> Result<(), Error> {
+        // Hold the cpu in reset after the boot rom
+        let mux = self.spi_bus.read_register(registers::rNMI_BOOT_RESET_MUX)?;
+        self.spi_bus
+            .write_register(registers::rNMI_BOOT_RESET_MUX, mux | 1)?;
+        // Bit 10 of the global reset register enables the cpu
+        let reset = self.spi_bus.read_register(registers::rNMI_GLB_RESET)?;
+        if reset & (1 << 10) != 0 {
+            self.chip_deinit()?;
+        }
+        Ok(())
+    }
+
+    /// Resets every block of the Atwinc1500 through
+    /// the global reset register. This turns crc
+    /// back on like a reset with the reset pin
+    ///
+    /// From chip_reset in the Atmel driver
+    fn chip_reset(&mut self) -> Result<(), Error> {
+        self.delay.delay_ms(50);
+        self.spi_bus.write_register(registers::rNMI_GLB_RESET, 0)?;
+        self.delay.delay_ms(50);
+        Ok(())
+    }
+
+    /// Clears the host interface and the driver
+    /// state after the Atwinc1500 was reset
+    fn clear_state(&mut self) -> Result<(), Error> {
+        self.spi_bus.crc_enabled()?;
+        self.hif = HostInterface::new(self.config.allocation_retries);
+        self.clear_driver_state();
+        Ok(())
+    }
+
+    /// Clears the driver state, keeping
+    /// the clock and counters
+    fn clear_driver_state(&mut self) {
+        let mut state = State::new();
+        state.now_ms = self.state.now_ms;
+        state.reconnect_count = self.state.reconnect_count;
+        state.last_disconnect_ms = self.state.last_disconnect_ms;
+        state.last_disconnect_reason = self.state.last_disconnect_reason;
+        state.dns_cache = self.state.dns_cache;
+        self.state = state;
+    }
+
+    /// Shuts the Atwinc1500 down before power is removed.
+    /// The network is disconnected, the chip interrupt is
+    /// disabled and the firmware is stopped. If hold_reset
+    /// is true the reset pin is left low afterwards to
+    /// reduce power use
+    ///
+    /// The driver can't be used again until recover is called
+    pub fn deinitialize(&mut self, hold_reset: bool) -> Result<(), Error> {
+        const DISCONNECT_TIMEOUT_MS: u32 = 1000;
+        if matches!(self.state.status, Status::Connected | Status::Connecting) {
+            self.disconnect_network()?;
+            // Wait for the firmware to finish disconnecting
+            // so it isn't stopped while writing to flash
+            let mut elapsed: u32 = 0;
+            while matches!(self.state.status, Status::Connected | Status::Connecting)
+                && elapsed < DISCONNECT_TIMEOUT_MS
+            {
+                self.handle_events()?;
+                self.delay.delay_ms(1);
+                elapsed += 1;
+            }
+        }
+        self.disable_chip_interrupt()?;
+        self.chip_deinit()?;
+        self.clear_driver_state();
+        if hold_reset && self.reset.set_low().is_err() {
+            return Err(Error::PinStateError);
+        }
+        Ok(())
+    }
+
+    /// Stops the firmware by clearing the cpu
+    /// enable bit of the global reset register
+    ///
+    /// From chip_deinit in the Atmel driver
+    fn chip_deinit(&mut self) -> Result<(), Error> {
+        let reset = self.spi_bus.read_register(registers::rNMI_GLB_RESET)?;
+        self.spi_bus
+            .write_register(registers::rNMI_GLB_RESET, reset & !(1 << 10))
+    }
+
+    /// Gets the oldest event processed by
+    /// handle_events that hasn't been read yet
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.state.events.pop()
+    }
+
+    /// Gets the ip configuration assigned by
+    /// dhcp, if the Atwinc1500 currently has one
+    pub fn get_ip_config(&self) -> Option<IpConfig> {
+        self.state.ip_config
+    }
+
+    /// Gets the connection status last
+    /// reported by the Atwinc1500
+    pub fn get_status(&self) -> Status {
+        self.state.status
+    }
+
+    /// Advances the clock used to measure
+    /// connection uptime. Call this periodically,
+    /// for example from a timer interrupt
+    pub fn tick(&mut self, elapsed_ms: u32) {
+        self.state.now_ms += elapsed_ms as u64;
+    }
+
+    /// Gets the connection status, rssi,
+    /// uptime and reconnect count together
+    pub fn get_link_health(&self) -> LinkHealth {
+        LinkHealth {
+            status: self.state.status,
+            rssi: self.state.rssi,
+            uptime_ms: self
+                .state
+                .connected_at_ms
+                .map(|connected_at| self.state.now_ms - connected_at),
+            reconnect_count: self.state.reconnect_count,
+        }
+    }
+
+    /// Gets the number of times the
+    /// connection has dropped
+    pub fn get_reconnect_count(&self) -> u32 {
+        self.state.reconnect_count
+    }
+
+    /// Gets the time of the last dropped
+    /// connection in milliseconds as counted by
+    /// tick, or None if it has never dropped
+    pub fn get_last_disconnect_ms(&self) -> Option<u64> {
+        self.state.last_disconnect_ms
+    }
+
+    /// Gets the error code sent with the last
+    /// disconnect or failed connection attempt,
+    /// or None if there hasn't been one
+    ///
+    /// The Atwinc1500 firmware does not pass the
+    /// 802.11 deauthentication or disassociation
+    /// reason code to the host. The state change
+    /// only carries a StateChangeErrorCode, and an
+    /// access point dropping the station arrives
+    /// as NoError
+    pub fn get_last_disconnect_reason(&self) -> Option<StateChangeErrorCode> {
+        self.state.last_disconnect_reason
+    }
+
+    /// Requests the rssi of the current connection.
+    /// The value is available from get_current_rssi
+    /// once it arrives
+    ///
+    /// The Atwinc1500 firmware does not report the
+    /// negotiated data rate of the link, rssi is the
+    /// only measure of link quality it exposes
+    pub fn request_current_rssi(&mut self) -> Result<(), Error> {
+        let hif_header = HifHeader::new(group_ids::WIFI, commands::wifi::REQ_CURRENT_RSSI, 0);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut [], &mut [])?;
+        Ok(())
+    }
+
+    /// Gets the last rssi in dBm
+    /// received from the Atwinc1500
+    pub fn get_current_rssi(&self) -> Option<i8> {
+        self.state.rssi
+    }
+
+    /// Requests len random bytes from the pseudo random
+    /// number generator of the Atwinc1500. They are
+    /// available from get_random_bytes once they arrive
+    ///
+    /// Returns Error::IndexOutOfRange if len is
+    /// 0 or more than types::RANDOM_BUFFER_SIZE
+    pub fn request_random_bytes(&mut self, len: usize) -> Result<(), Error> {
+        if len == 0 || len > RANDOM_BUFFER_SIZE {
+            return Err(Error::IndexOutOfRange);
+        }
+        // buffer pointer (4 bytes), size (2 bytes), padding (2 bytes).
+        // The firmware sends the pointer back unused
+        let size = (len as u16).to_le_bytes();
+        let mut data: [u8; 8] = [0, 0, 0, 0, size[0], size[1], 0, 0];
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_GET_PRNG,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        self.state.random_len = None;
+        Ok(())
+    }
+
+    /// Gets the random bytes from the
+    /// last request_random_bytes
+    pub fn get_random_bytes(&self) -> Option<&[u8]> {
+        self.state
+            .random_len
+            .map(|len| &self.state.random_bytes[..len])
+    }
+
+    /// Fills buf with random bytes from the Atwinc1500,
+    /// blocking until they arrive. Buffers larger than
+    /// types::RANDOM_BUFFER_SIZE are filled with several
+    /// requests. Fails with Error::RandomTimeout if buf
+    /// isn't filled within timeout_ms
+    pub fn fill_random(&mut self, buf: &mut [u8], timeout_ms: u32) -> Result<(), Error> {
+        let mut elapsed: u32 = 0;
+        let mut filled: usize = 0;
+        while filled < buf.len() {
+            let len = (buf.len() - filled).min(RANDOM_BUFFER_SIZE);
+            self.request_random_bytes(len)?;
+            loop {
+                self.handle_events()?;
+                if let Some(bytes) = self.get_random_bytes() {
+                    let count = bytes.len().min(buf.len() - filled);
+                    buf[filled..filled + count].copy_from_slice(&bytes[..count]);
+                    filled += count;
+                    break;
+                }
+                if elapsed >= timeout_ms {
+                    return Err(Error::RandomTimeout);
+                }
+                self.delay.delay_ms(1);
+                elapsed += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a tcp socket. The handle is picked
+    /// by the driver, so nothing is sent to the
+    /// Atwinc1500 until the socket is used
+    ///
+    /// Returns Error::NoFreeSockets when all
+    /// socket::TCP_SOCK_MAX sockets are open
+    pub fn tcp_socket(&mut self) -> Result<TcpSocket, Error> {
+        let handle = self
+            .state
+            .sockets
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(Error::NoFreeSockets)?;
+        self.state.next_session = self.state.next_session.wrapping_add(1);
+        self.state.sockets[handle] = Some(SocketState::new(self.state.next_session));
+        self.state.socket_readiness.clear(handle as u8);
+        Ok(TcpSocket {
+            handle: handle as u8,
+            tls: false,
+        })
+    }
+
+    /// Closes a socket and frees its handle. The
+    /// handle is freed even if the close request
+    /// can't be sent
+    pub fn close_socket(&mut self, socket: TcpSocket) -> Result<(), Error> {
+        let handle = socket.handle as usize;
+        let session = match self.state.sockets.get_mut(handle).and_then(|s| s.take()) {
+            Some(s) => s.session,
+            None => return Ok(()),
+        };
+        self.state.socket_readiness.clear(socket.handle);
+        // socket, padding, session (2 bytes)
+        let session = session.to_le_bytes();
+        let mut data: [u8; 4] = [socket.handle, 0, session[0], session[1]];
+        let command = socket.command(SocketCommand::Close);
+        let hif_header = HifHeader::new(group_ids::IP, command as u8, data.len() as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Binds a tcp socket to port on every address of
+    /// the Atwinc1500. If the firmware rejects the bind
+    /// the socket becomes closed
+    pub fn tcp_bind(&mut self, socket: &TcpSocket, port: u16) -> Result<(), Error> {
+        let session = self.open_socket(socket)?.session.to_le_bytes();
+        // address (8 bytes), socket, padding, session (2 bytes)
+        let mut cmd: [u8; 12] = [0; 12];
+        cmd[..socket::SOCKADDR_SIZE]
+            .copy_from_slice(&socket::sockaddr_to_bytes(Ipv4Addr::UNSPECIFIED, port));
+        cmd[8] = socket.handle;
+        cmd[10..12].copy_from_slice(&session);
+        let hif_header = HifHeader::new(group_ids::IP, SocketCommand::Bind as u8, cmd.len() as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut cmd, &mut [])?;
+        Ok(())
+    }
+
+    /// Starts accepting connections on a bound tcp
+    /// socket. Connections are taken with tcp_accept
+    pub fn tcp_listen(&mut self, socket: &TcpSocket) -> Result<(), Error> {
+        const BACKLOG: u8 = 1;
+        let session = self.open_socket(socket)?.session.to_le_bytes();
+        // socket, backlog, session (2 bytes)
+        let mut cmd: [u8; 4] = [socket.handle, BACKLOG, session[0], session[1]];
+        let hif_header =
+            HifHeader::new(group_ids::IP, SocketCommand::Listen as u8, cmd.len() as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut cmd, &mut [])?;
+        Ok(())
+    }
+
+    /// Handles pending events then takes a connection
+    /// accepted by a listening socket, with the address
+    /// of its peer. The Atwinc1500 picks the handle of
+    /// the connection. Returns Ok(None) if no connection
+    /// is waiting, and Error::SocketClosed if the bind
+    /// or listen failed
+    pub fn tcp_accept(
+        &mut self,
+        listener: &TcpSocket,
+    ) -> Result<Option<(TcpSocket, SocketAddrV4)>, Error> {
+        self.handle_events()?;
+        if self.open_socket(listener)?.closed {
+            return Err(Error::SocketClosed);
+        }
+        let mut pending = self
+            .state
+            .sockets
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, s)| {
+                s.as_mut()
+                    .filter(|s| s.accepted_by == Some(listener.handle))
+                    .map(|s| (i, s))
+            });
+        let accepted = pending.next().map(|(handle, s)| {
+            s.accepted_by = None;
+            let peer = s
+                .peer
+                .unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+            (
+                TcpSocket {
+                    handle: handle as u8,
+                    tls: false,
+                },
+                peer,
+            )
+        });
+        if pending.next().is_none() {
+            self.state.socket_readiness.clear_readable(listener.handle);
+        }
+        Ok(accepted)
+    }
+
+    /// Limits the cipher suites the Atwinc1500 offers
+    /// in tls handshakes, for example to only the
+    /// ecdhe suites. The firmware keeps one list for
+    /// every tls socket, so it applies to connections
+    /// made after it is set rather than to one socket
+    ///
+    /// Returns Error::InvalidSocketOption
+    /// if suites is empty
+    pub fn set_cipher_suites(&mut self, suites: &[CipherSuite]) -> Result<(), Error> {
+        let bitmap = suites.iter().fold(0u32, |bits, suite| bits | *suite as u32);
+        if bitmap == 0 {
+            return Err(Error::InvalidSocketOption);
+        }
+        // cipher suite bitmap (4 bytes)
+        let mut data = bitmap.to_le_bytes();
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            SocketCommand::SslSetCsList as u8,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Handles pending events then connects a tcp
+    /// socket to addr over tls, sending server_name
+    /// for sni and certificate verification. The
+    /// first call sends the requests, call it again
+    /// until it returns Ok(Some(())). Sends, receives
+    /// and the close of the socket then use the ssl
+    /// socket commands
+    ///
+    /// Returns Error::TlsHandshakeFailed if the
+    /// Atwinc1500 couldn't complete the handshake,
+    /// for example when the server's certificate
+    /// isn't trusted by the root certificates in
+    /// its flash, and Error::HostnameTooLong for
+    /// server names the firmware can't hold
+    pub fn connect_tls(
+        &mut self,
+        socket: &mut TcpSocket,
+        addr: SocketAddrV4,
+        server_name: &str,
+    ) -> Result<Option<()>, Error> {
+        self.handle_events()?;
+        let state = self.open_socket(socket)?;
+        if state.handshake_failed {
+            return Err(Error::TlsHandshakeFailed);
+        }
+        if state.connected {
+            return Ok(Some(()));
+        }
+        if state.connecting {
+            return Ok(None);
+        }
+        if state.closed {
+            return Err(Error::SocketClosed);
+        }
+        let name = server_name.as_bytes();
+        // The firmware needs room for a null terminator
+        if name.len() >= socket::SSL_MAX_OPT_LEN {
+            return Err(Error::HostnameTooLong);
+        }
+        let session = state.session.to_le_bytes();
+        let handle = socket.handle;
+        // socket, padding (3 bytes)
+        let mut create: [u8; 4] = [handle, 0, 0, 0];
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            SocketCommand::SslCreate as u8,
+            create.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut create, &mut [])?;
+        // socket, option, session (2 bytes), option
+        // length (4 bytes), option value (64 bytes)
+        let mut sni: [u8; 8 + socket::SSL_MAX_OPT_LEN] = [0; 8 + socket::SSL_MAX_OPT_LEN];
+        sni[0] = handle;
+        sni[1] = socket::ssl_options::SO_SSL_SNI;
+        sni[2..4].copy_from_slice(&session);
+        sni[4..8].copy_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+        sni[8..8 + name.len()].copy_from_slice(name);
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            SocketCommand::SslSetSockOpt as u8,
+            sni.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut sni, &mut [])?;
+        // address (8 bytes), socket, ssl flags, session (2 bytes)
+        let mut cmd: [u8; 12] = [0; 12];
+        cmd[..socket::SOCKADDR_SIZE]
+            .copy_from_slice(&socket::sockaddr_to_bytes(*addr.ip(), addr.port()));
+        cmd[8] = handle;
+        cmd[9] = socket::ssl_flags::SSL_FLAGS_ACTIVE | socket::ssl_flags::SSL_FLAGS_NO_TX_COPY;
+        cmd[10..12].copy_from_slice(&session);
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            SocketCommand::SslConnect as u8,
+            cmd.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut cmd, &mut [])?;
+        if let Some(Some(state)) = self.state.sockets.get_mut(handle as usize) {
+            state.connecting = true;
+            state.tx_offset = socket::SSL_TX_PACKET_OFFSET;
+        }
+        socket.tls = true;
+        Ok(None)
+    }
+
+    /// Gets the state of a socket still in the pool
+    fn open_socket(&self, socket: &TcpSocket) -> Result<&SocketState, Error> {
+        match self.state.sockets.get(socket.handle as usize) {
+            Some(Some(s)) => Ok(s),
+            _ => Err(Error::SocketNotOpen),
+        }
+    }
+
+    /// Turns on tcp keepalive for a socket, so the
+    /// Atwinc1500 sends a probe after idle_ms without
+    /// traffic and every interval_ms after that. This
+    /// keeps nat mappings of long lived connections
+    /// open. An idle_ms of 0 turns keepalive off
+    ///
+    /// Times are rounded up to the firmware's 500ms
+    /// units, longer than socket::MAX_KEEPALIVE_IDLE_MS
+    /// or socket::MAX_KEEPALIVE_INTERVAL_MS returns
+    /// Error::InvalidSocketOption
+    ///
+    /// This requires firmware 19.6.1 or newer,
+    /// older firmware returns UnsupportedFirmware
+    pub fn set_socket_keepalive(
+        &mut self,
+        socket: &TcpSocket,
+        idle_ms: u32,
+        interval_ms: u32,
+    ) -> Result<(), Error> {
+        if !self.supported_features().tcp_keepalive {
+            return Err(Error::UnsupportedFirmware);
+        }
+        if idle_ms == 0 {
+            return self.set_socket_option(socket, socket::options::SO_TCP_KEEPALIVE, 0);
+        }
+        if idle_ms > socket::MAX_KEEPALIVE_IDLE_MS
+            || interval_ms == 0
+            || interval_ms > socket::MAX_KEEPALIVE_INTERVAL_MS
+        {
+            return Err(Error::InvalidSocketOption);
+        }
+        let idle = idle_ms.div_ceil(socket::KEEPALIVE_UNIT_MS);
+        let interval = interval_ms.div_ceil(socket::KEEPALIVE_UNIT_MS);
+        self.set_socket_option(socket, socket::options::SO_TCP_KEEPIDLE, idle)?;
+        self.set_socket_option(socket, socket::options::SO_TCP_KEEPINTVL, interval)?;
+        self.set_socket_option(socket, socket::options::SO_TCP_KEEPALIVE, 1)
+    }
+
+    /// Sends a socket option to the Atwinc1500
+    fn set_socket_option(
+        &mut self,
+        socket: &TcpSocket,
+        option: u8,
+        value: u32,
+    ) -> Result<(), Error> {
+        let session = self.open_socket(socket)?.session.to_le_bytes();
+        let value = value.to_le_bytes();
+        // socket, option, session (2 bytes), value (4 bytes)
+        let mut data: [u8; 8] = [
+            socket.handle,
+            option,
+            session[0],
+            session[1],
+            value[0],
+            value[1],
+            value[2],
+            value[3],
+        ];
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            SocketCommand::SetSocketOption as u8,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Sends data on a connected tcp socket and returns
+    /// how many bytes were accepted. Up to
+    /// socket::SOCKET_BUFFER_MAX_LENGTH bytes are sent
+    /// at once, so call it again with the rest
+    ///
+    /// Returns Ok(None) if the Atwinc1500 has no buffer
+    /// free for the data, and Error::SocketClosed once
+    /// the peer has closed the socket
+    pub fn tcp_send(&mut self, socket: &TcpSocket, data: &[u8]) -> Result<Option<usize>, Error> {
+        let (session, offset) = match self.state.sockets.get(socket.handle as usize) {
+            Some(Some(s)) if s.handshake_failed => return Err(Error::TlsHandshakeFailed),
+            Some(Some(s)) if s.closed => return Err(Error::SocketClosed),
+            Some(Some(s)) => (s.session.to_le_bytes(), s.tx_offset),
+            _ => return Err(Error::SocketNotOpen),
+        };
+        let len = data.len().min(socket::SOCKET_BUFFER_MAX_LENGTH);
+        if len == 0 {
+            return Ok(Some(0));
+        }
+        let size = (len as u16).to_le_bytes();
+        // socket, padding, size (2 bytes), address (8 bytes),
+        // session (2 bytes), padding (2 bytes)
+        let mut cmd: [u8; 16] = [0; 16];
+        cmd[0] = socket.handle;
+        cmd[2..4].copy_from_slice(&size);
+        cmd[12..14].copy_from_slice(&session);
+        // The transfer overwrites the buffer it sends
+        let mut payload: [u8; socket::SOCKET_BUFFER_MAX_LENGTH] =
+            [0; socket::SOCKET_BUFFER_MAX_LENGTH];
+        payload[..len].copy_from_slice(&data[..len]);
+        let command = socket.command(SocketCommand::Send);
+        let hif_header = HifHeader::new(
+            group_ids::IP,
+            command as u8 | REQ_DATA_PKT,
+            (offset + len) as u16,
+        );
+        match self.hif.send_at(
+            &mut self.spi_bus,
+            hif_header,
+            &mut cmd,
+            &mut payload[..len],
+            offset,
+        ) {
+            Ok(_) => Ok(Some(len)),
+            Err(Error::HifError(HifError::AllocationFailed)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Handles pending events then moves received data
+    /// into data and returns how many bytes were moved.
+    /// If nothing has arrived a receive request is sent
+    /// and Ok(None) is returned, call it again after the
+    /// socket is readable
+    ///
+    /// Returns Ok(Some(0)) once the peer has closed the
+    /// socket and everything it sent has been read.
+    /// Returns Error::SocketBufferOverflow once, after
+    /// the buffered data, if data was dropped because
+    /// more arrived than fits in
+    /// socket::SOCKET_RX_BUFFER_SIZE
+    pub fn tcp_receive(
+        &mut self,
+        socket: &TcpSocket,
+        data: &mut [u8],
+    ) -> Result<Option<usize>, Error> {
+        self.handle_events()?;
+        let handle = socket.handle;
+        let state = match self.state.sockets.get_mut(handle as usize) {
+            Some(Some(s)) => s,
+            _ => return Err(Error::SocketNotOpen),
+        };
+        if state.handshake_failed {
+            return Err(Error::TlsHandshakeFailed);
+        }
+        if !state.rx.is_empty() {
+            let count = state.rx.read(data);
+            if state.rx.is_empty() {
+                self.state.socket_readiness.clear_readable(handle);
+            }
+            return Ok(Some(count));
+        }
+        if state.overflow {
+            state.overflow = false;
+            return Err(Error::SocketBufferOverflow);
+        }
+        if state.closed {
+            return Ok(Some(0));
+        }
+        if state.recv_pending {
+            return Ok(None);
+        }
+        let session = state.session.to_le_bytes();
+        // The firmware sends at most this many bytes
+        let free = (state.rx.free() as u16).to_le_bytes();
+        // timeout (4 bytes), socket, padding, session
+        // (2 bytes), buffer size (2 bytes), padding (2 bytes).
+        // A timeout of 0xffffffff waits until data arrives
+        let mut cmd: [u8; 12] = [
+            0xff, 0xff, 0xff, 0xff, handle, 0, session[0], session[1], free[0], free[1], 0, 0,
+        ];
+        let command = socket.command(SocketCommand::Recv);
+        let hif_header = HifHeader::new(group_ids::IP, command as u8, cmd.len() as u16);
+        // The request is sent again on the next call
+        // if the Atwinc1500 had no buffer free for it
+        match self
+            .hif
+            .send(&mut self.spi_bus, hif_header, &mut cmd, &mut [])
+        {
+            Ok(_) => {}
+            Err(Error::HifError(HifError::AllocationFailed)) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        if let Some(Some(state)) = self.state.sockets.get_mut(handle as usize) {
+            state.recv_pending = true;
+        }
+        Ok(None)
+    }
+
+    /// Gets how many received bytes a socket holds,
+    /// which tcp_receive returns without waiting on
+    /// the Atwinc1500. The firmware only reports
+    /// data with the reply to a receive request, so
+    /// bytes it hasn't sent yet aren't counted. Call
+    /// handle_events first to include replies that
+    /// have arrived. Sockets that aren't open hold 0
+    pub fn bytes_available(&self, socket: &TcpSocket) -> usize {
+        match self.state.sockets.get(socket.handle as usize) {
+            Some(Some(s)) => s.rx.len(),
+            _ => 0,
+        }
+    }
+
+    /// Handles pending events then gets which of
+    /// sockets are readable, writable or closed,
+    /// so several sockets can be serviced without
+    /// polling each one for WouldBlock
+    pub fn poll_sockets(&mut self, sockets: &[&TcpSocket]) -> Result<SocketReadiness, Error> {
+        self.handle_events()?;
+        Ok(self.state.socket_readiness.select(sockets))
+    }
+
+    /// Requests details of the current connection.
+    /// The value is available from get_connection_info
+    /// once it arrives
+    pub fn request_connection_info(&mut self) -> Result<(), Error> {
+        let hif_header = HifHeader::new(group_ids::WIFI, commands::wifi::REQ_GET_CONN_INFO, 0);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut [], &mut [])?;
+        Ok(())
+    }
+
+    /// Gets the last connection info received
+    /// from the Atwinc1500. This is cleared
+    /// when the connection drops
+    pub fn get_connection_info(&self) -> Option<ConnectionInfo> {
+        self.state.conn_info
+    }
+
+    /// Gets the mac address of the access point
+    /// from the last connection info. Request it again
+    /// with request_connection_info after roaming
+    pub fn get_current_bssid(&self) -> Option<MacAddress> {
+        self.state.conn_info.map(|info| info.bssid)
+    }
+
+    /// Gets the channel the Atwinc1500 is on
+    /// from the last connection info. Request it again
+    /// with request_connection_info after roaming
+    pub fn get_current_channel(&self) -> Option<u8> {
+        self.state.conn_info.map(|info| info.channel)
+    }
+
+    /// Sets the region which limits the
+    /// channels the Atwinc1500 will use
+    pub fn set_scan_region(&mut self, region: ScanRegion) -> Result<(), Error> {
+        let region = region as u16;
+        // region (2 bytes), padding (2 bytes)
+        let mut data: [u8; 4] = [region as u8, (region >> 8) as u8, 0, 0];
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_SET_SCAN_REGION,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Sets the transmit power level
+    pub fn set_tx_power(&mut self, level: TxPower) -> Result<(), Error> {
+        // level, padding (3 bytes)
+        let mut data: [u8; 4] = [level as u8, 0, 0, 0];
         let hif_header = HifHeader::new(
             group_ids::WIFI,
-            commands::wifi::REQ_CONNECT,
-            conn_header.len() as u16,
+            commands::wifi::REQ_SET_TX_POWER,
+            data.len() as u16,
         );
         self.hif
-            .send(&mut self.spi_bus, hif_header, &mut conn_header, &mut [])?;
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        self.state.tx_power = Some(level);
+        Ok(())
+    }
+
+    /// Lets frames sent to a multicast mac address
+    /// through, for example 01:00:5e:00:00:fb for mdns.
+    /// Adding an address that is already set does
+    /// nothing. Returns Error::IndexOutOfRange when
+    /// wifi::MAX_MULTICAST_FILTERS are set
+    pub fn add_multicast_filter(&mut self, mac: MacAddress) -> Result<(), Error> {
+        let count = self.state.multicast_count;
+        if self.state.multicast_filters[..count].contains(&mac) {
+            return Ok(());
+        }
+        if count == MAX_MULTICAST_FILTERS {
+            return Err(Error::IndexOutOfRange);
+        }
+        self.set_multicast_filter(mac, true)?;
+        self.state.multicast_filters[count] = mac;
+        self.state.multicast_count += 1;
+        Ok(())
+    }
+
+    /// Stops letting frames sent to a multicast
+    /// mac address through
+    pub fn remove_multicast_filter(&mut self, mac: MacAddress) -> Result<(), Error> {
+        let count = self.state.multicast_count;
+        let index = match self.state.multicast_filters[..count]
+            .iter()
+            .position(|m| *m == mac)
+        {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        self.set_multicast_filter(mac, false)?;
+        self.state
+            .multicast_filters
+            .copy_within(index + 1..count, index);
+        self.state.multicast_count -= 1;
+        Ok(())
+    }
+
+    /// Gets the multicast mac addresses set with
+    /// add_multicast_filter. The firmware can't report
+    /// its filters, so this is the list the driver
+    /// sent. It is emptied when the Atwinc1500 is reset
+    pub fn get_multicast_filters(&self) -> &[MacAddress] {
+        &self.state.multicast_filters[..self.state.multicast_count]
+    }
+
+    /// Sends a multicast mac address to add or remove
+    fn set_multicast_filter(&mut self, mac: MacAddress, add: bool) -> Result<(), Error> {
+        // mac address (6 bytes), add or remove, padding
+        let mut data: [u8; 8] = [0; 8];
+        data[..6].copy_from_slice(&mac.0);
+        data[6] = add as u8;
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_SET_MAC_MCAST,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Gets the transmit power level last set with
+    /// set_tx_power, or None if it hasn't been set
+    /// since the Atwinc1500 was initialized, which
+    /// means it is at the default of TxPower::High
+    ///
+    /// The firmware has no request for reading the
+    /// level back, so this is the level the driver
+    /// sent and not one read from the Atwinc1500
+    pub fn get_tx_power(&self) -> Option<TxPower> {
+        self.state.tx_power
+    }
+
+    /// Sets the scan region for an ISO 3166
+    /// country code, see ScanRegion::from_country.
+    /// Only the allowed channels are affected, the
+    /// firmware does not adjust transmit power by region
+    pub fn set_country(&mut self, code: [u8; 2]) -> Result<(), Error> {
+        self.set_scan_region(ScanRegion::from_country(code)?)
+    }
+
+    /// Sets how long the Atwinc1500 listens on
+    /// each channel during later scans
+    pub fn set_scan_options(&mut self, options: &ScanOptions) -> Result<(), Error> {
+        // number of slots, slot time, probes per slot, rssi threshold
+        let mut data: [u8; 4] = options.to_bytes()?;
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_SET_SCAN_OPTION,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Sets the shortest scan options then requests
+    /// a scan on a single channel, for quickly checking
+    /// that a network is still there
+    ///
+    /// The short options stay set for later scans,
+    /// restore them with set_scan_options and
+    /// ScanOptions::default()
+    pub fn quick_scan_channel(&mut self, channel: Channel) -> Result<(), Error> {
+        if matches!(channel, Channel::Any) {
+            return Err(Error::InvalidChannel);
+        }
+        if self.state.scan_in_progress {
+            return Err(Error::ScanInProgress);
+        }
+        self.set_scan_options(&ScanOptions::QUICK)?;
+        self.request_network_scan(channel)
+    }
+
+    /// Requests a scan for wireless networks on
+    /// the given channel. The number of networks
+    /// found is available from get_num_ap once
+    /// the scan is done
+    ///
+    /// Scanning while connected keeps the connection,
+    /// but the Atwinc1500 leaves the network's channel
+    /// for each scan slot so traffic is delayed until
+    /// the scan is done. The status only changes if the
+    /// Atwinc1500 reports that the connection dropped
+    pub fn request_network_scan(&mut self, channel: Channel) -> Result<(), Error> {
+        if self.state.scan_in_progress {
+            return Err(Error::ScanInProgress);
+        }
+        // channel, reserved, passive scan time (2 bytes)
+        let mut data: [u8; 4] = [channel as u8, 0, 0, 0];
+        let hif_header =
+            HifHeader::new(group_ids::WIFI, commands::wifi::REQ_SCAN, data.len() as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        self.state.scan_in_progress = true;
+        self.state.num_ap = 0;
+        self.state.scan_results = [None; MAX_SCAN_RESULTS];
         Ok(())
     }
 
+    /// Gets a copy of the driver's internal state
+    /// for white box tests. Only built with the
+    /// test-internals feature, the fields may
+    /// change between releases
+    #[cfg(feature = "test-internals")]
+    pub fn debug_state(&self) -> DebugState {
+        self.state.debug_state()
+    }
+
+    /// Returns true from the time a scan is
+    /// requested until the Atwinc1500 reports
+    /// that it is done
+    pub fn is_scan_in_progress(&self) -> bool {
+        self.state.scan_in_progress
+    }
+
+    /// Returns true from the time a dns resolve
+    /// is requested until the Atwinc1500 responds.
+    /// Requesting another fails with
+    /// Error::DnsInProgress
+    pub fn is_dns_in_progress(&self) -> bool {
+        self.state.dns_in_progress
+    }
+
+    /// Asks the Atwinc1500 to resolve hostname. The
+    /// address arrives with Event::DnsResolved and is
+    /// read with get_resolved_address. Fails with
+    /// Error::HostnameTooLong for names the firmware
+    /// can't hold and Error::DnsInProgress until the
+    /// last resolve finishes
+    pub fn request_dns_resolve(&mut self, hostname: &str) -> Result<(), Error> {
+        let host = hostname.as_bytes();
+        // The firmware needs room for a null terminator
+        if host.len() >= dns::HOSTNAME_MAX_SIZE {
+            return Err(Error::HostnameTooLong);
+        }
+        if self.state.dns_in_progress {
+            return Err(Error::DnsInProgress);
+        }
+        let mut data: [u8; dns::HOSTNAME_MAX_SIZE] = [0; dns::HOSTNAME_MAX_SIZE];
+        data[..host.len()].copy_from_slice(host);
+        let len = host.len() + 1;
+        let hif_header = HifHeader::new(group_ids::IP, SocketCommand::DnsResolve as u8, len as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data[..len], &mut [])?;
+        self.state.dns_host = data;
+        self.state.dns_host_len = host.len();
+        self.state.dns_result = None;
+        self.state.dns_in_progress = true;
+        Ok(())
+    }
+
+    /// Gets the address from the last
+    /// request_dns_resolve. This is None until
+    /// the Atwinc1500 responds and when the
+    /// host wasn't found
+    pub fn get_resolved_address(&self) -> Option<Ipv4Addr> {
+        self.state.dns_result.filter(|ip| !ip.is_unspecified())
+    }
+
+    /// Resolves hostname without blocking, returning
+    /// Ok(None) until the address arrives. Hosts in the
+    /// dns cache are returned without a request. Fails
+    /// with Error::HostNotFound if the Atwinc1500 can't
+    /// resolve the host and Error::DnsInProgress while
+    /// another host is being resolved
+    pub fn resolve_host(&mut self, hostname: &str) -> Result<Option<Ipv4Addr>, Error> {
+        let host = hostname.as_bytes();
+        if let Some(ip) = self.get_cached_host(host) {
+            return Ok(Some(ip));
+        }
+        self.handle_events()?;
+        let requested = &self.state.dns_host[..self.state.dns_host_len] == host;
+        if self.state.dns_in_progress {
+            if requested {
+                return Ok(None);
+            }
+            return Err(Error::DnsInProgress);
+        }
+        match self.state.dns_result {
+            Some(ip) if requested => {
+                // Forget the result so resolving
+                // the host again sends a request
+                self.state.dns_host_len = 0;
+                if ip.is_unspecified() {
+                    return Err(Error::HostNotFound);
+                }
+                Ok(Some(ip))
+            }
+            _ => {
+                self.request_dns_resolve(hostname)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Gets the address of a host resolved by the
+    /// Atwinc1500 within the cache ttl, without sending
+    /// a request. The ttl is measured with the clock
+    /// advanced by tick
+    pub fn get_cached_host(&self, host: &[u8]) -> Option<Ipv4Addr> {
+        self.state.dns_cache.get(host, self.state.now_ms)
+    }
+
+    /// Sets how long resolved hosts are cached in
+    /// milliseconds. The firmware doesn't pass on the
+    /// ttl of dns records, so the default is
+    /// dns::DEFAULT_DNS_CACHE_TTL_MS. A ttl of 0
+    /// stops hosts from being cached
+    pub fn set_dns_cache_ttl(&mut self, ttl_ms: u32) {
+        self.state.dns_cache.set_ttl(ttl_ms);
+    }
+
+    /// Removes every host from the dns cache
+    pub fn clear_dns_cache(&mut self) {
+        self.state.dns_cache.clear();
+    }
+
+    /// Returns true from the time a ping is
+    /// requested until the Atwinc1500 responds.
+    /// Requesting another fails with
+    /// Error::PingInProgress
+    pub fn is_ping_in_progress(&self) -> bool {
+        self.state.ping_in_progress
+    }
+
+    /// Sends count icmp echo requests to addr with
+    /// the time to live ttl. The result arrives with
+    /// Event::PingDone and is read with get_ping_result.
+    /// Fails with Error::PingInProgress until the last
+    /// ping finishes
+    pub fn request_ping(&mut self, addr: Ipv4Addr, count: u16, ttl: u8) -> Result<(), Error> {
+        if self.state.ping_in_progress {
+            return Err(Error::PingInProgress);
+        }
+        // ip address, private (4 bytes), count (2 bytes),
+        // ttl, padding. The firmware sends private back unused
+        let mut data: [u8; 12] = [0; 12];
+        data[..4].copy_from_slice(&socket::ipv4_to_bytes(addr));
+        data[8..10].copy_from_slice(&count.to_le_bytes());
+        data[10] = ttl;
+        let hif_header =
+            HifHeader::new(group_ids::IP, SocketCommand::Ping as u8, data.len() as u16);
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        self.state.ping_result = None;
+        self.state.ping_in_progress = true;
+        Ok(())
+    }
+
+    /// Gets the result of the last request_ping,
+    /// None until the ping finishes
+    pub fn get_ping_result(&self) -> Option<PingResult> {
+        self.state.ping_result
+    }
+
+    /// Gets the number of networks found in the
+    /// last scan. This is 0 while a scan is in
+    /// progress, check is_scan_in_progress or wait
+    /// for Event::ScanDone to tell an empty scan
+    /// from an unfinished one
+    pub fn get_num_ap(&self) -> u8 {
+        self.state.num_ap
+    }
+
+    /// Requests the scan result at index from
+    /// the last scan. The result is available
+    /// from get_scan_result once it arrives
+    pub fn request_scan_result(&mut self, index: u8) -> Result<(), Error> {
+        if self.state.scan_in_progress {
+            return Err(Error::ScanInProgress);
+        }
+        if index >= self.state.num_ap {
+            return Err(Error::IndexOutOfRange);
+        }
+        // index, padding (3 bytes)
+        let mut data: [u8; 4] = [index, 0, 0, 0];
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_SCAN_RESULT,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
+
+    /// Gets the last scan result
+    /// received from the Atwinc1500
+    pub fn get_scan_result(&self) -> Option<ScanResult> {
+        self.state.scan_result
+    }
+
+    /// Clamps the number of networks found in the
+    /// last scan to the capacity of a buffer. Iterate
+    /// request_scan_result up to the returned count,
+    /// is_truncated tells if any results were left out
+    pub fn get_scan_count(&self, capacity: usize) -> ScanCount {
+        ScanCount::new(self.state.num_ap, capacity)
+    }
+
+    /// Requests every scan result from the last scan,
+    /// up to MAX_SCAN_RESULTS. Results are collected
+    /// by handle_events and read with get_scan_results
+    pub fn request_all_scan_results(&mut self) -> Result<ScanCount, Error> {
+        let scan_count = self.get_scan_count(MAX_SCAN_RESULTS);
+        for index in 0..scan_count.count {
+            self.request_scan_result(index)?;
+        }
+        Ok(scan_count)
+    }
+
+    /// Gets the scan results from the last scan indexed
+    /// by scan result index. A result is None until it
+    /// has been requested and received
+    pub fn get_scan_results(&self) -> &[Option<ScanResult>] {
+        let count = self.get_scan_count(MAX_SCAN_RESULTS).count;
+        &self.state.scan_results[..count as usize]
+    }
+
+    /// Connects to a wireless network
+    /// given a ConnectionParameters struct
+    ///
+    /// Parameters with a bssid are sent in the newer
+    /// connection format, which needs firmware 19.6.1
+    /// or newer. Older firmware returns
+    /// Error::UnsupportedFirmware
+    pub fn connect_network(&mut self, connection: &ConnectionParameters) -> Result<(), Error> {
+        self.send_connection(connection)
+    }
+
+    /// Connects to a wireless network and blocks
+    /// until the Atwinc1500 reports the connection
+    /// succeeded or failed, or timeout_ms elapses
+    ///
+    /// The firmware's connect request has no timeout
+    /// field, so the limit is enforced on the host. When
+    /// timeout_ms elapses a disconnect is sent to make
+    /// the Atwinc1500 abandon the attempt
+    pub fn connect_network_blocking(
+        &mut self,
+        connection: &ConnectionParameters,
+        timeout_ms: u32,
+    ) -> Result<(), Error> {
+        self.connect_network(connection)?;
+        self.wait_for_connection(timeout_ms)
+    }
+
+    /// Connects to a wireless network the same way as
+    /// connect_network_blocking, but if an attempt fails
+    /// or times out the connection is sent again, up to
+    /// `retries` more times
+    pub fn connect_network_with_retries(
+        &mut self,
+        connection: &ConnectionParameters,
+        retries: u8,
+        per_try_timeout_ms: u32,
+    ) -> Result<(), Error> {
+        let mut retries_left = retries;
+        loop {
+            self.send_connection(connection)?;
+            match self.wait_for_connection(per_try_timeout_ms) {
+                Err(Error::ConnectionFailed) | Err(Error::ConnectionTimeout)
+                    if retries_left > 0 => {}
+                result => return result,
+            }
+            retries_left -= 1;
+        }
+    }
+
+    /// Sends a connection to the Atwinc1500. Only the
+    /// newer connection format has a bssid, so it is
+    /// used when the parameters have one
+    fn send_connection(&mut self, connection: &ConnectionParameters) -> Result<(), Error> {
+        if connection.bssid().is_some() {
+            if !self.supported_features().new_connection_format {
+                return Err(Error::UnsupportedFirmware);
+            }
+            let (mut conn_header, mut psk): NewConnection = connection.into();
+            // The passphrase follows the header as a data packet
+            let (opcode, psk) = match connection {
+                ConnectionParameters::WpaPsk(..) => {
+                    (commands::wifi::REQ_CONN | REQ_DATA_PKT, &mut psk[..])
+                }
+                _ => (commands::wifi::REQ_CONN, &mut [][..]),
+            };
+            let hif_header = HifHeader::new(
+                group_ids::WIFI,
+                opcode,
+                (conn_header.len() + psk.len()) as u16,
+            );
+            self.hif
+                .send(&mut self.spi_bus, hif_header, &mut conn_header, psk)?;
+        } else {
+            let mut conn_header: OldConnection = connection.into();
+            let hif_header = HifHeader::new(
+                group_ids::WIFI,
+                commands::wifi::REQ_CONNECT,
+                conn_header.len() as u16,
+            );
+            self.hif
+                .send(&mut self.spi_bus, hif_header, &mut conn_header, &mut [])?;
+        }
+        self.state.status = Status::Connecting;
+        Ok(())
+    }
+
+    /// Handles events until the status leaves
+    /// Connecting or timeout_ms elapses. On timeout
+    /// the connection attempt is abandoned
+    fn wait_for_connection(&mut self, timeout_ms: u32) -> Result<(), Error> {
+        let mut elapsed: u32 = 0;
+        loop {
+            self.handle_events()?;
+            match self.state.status {
+                Status::Connected => return Ok(()),
+                Status::Connecting => {}
+                _ => return Err(Error::ConnectionFailed),
+            }
+            if elapsed >= timeout_ms {
+                self.disconnect_network()?;
+                self.state.status = Status::Disconnected;
+                self.state.disconnect_pending = true;
+                return Err(Error::ConnectionTimeout);
+            }
+            self.delay.delay_ms(1);
+            elapsed += 1;
+        }
+    }
+
     /// Disconnects from a wireless network
     pub fn disconnect_network(&mut self) -> Result<(), Error> {
         let hif_header = HifHeader::new(group_ids::WIFI, commands::wifi::REQ_DISCONNECT, 0);
@@ -281,14 +1813,44 @@ where
     }
 
     /// Connects to the last remembered network
+    ///
+    /// The firmware has no request for reading back the
+    /// remembered network. The ssid can be read with
+    /// request_connection_info once connected
     pub fn connect_default_network(&mut self) -> Result<(), Error> {
         let hif_header = HifHeader::new(group_ids::WIFI, commands::wifi::REQ_DEFAULT_CONNECT, 0);
         self.hif
             .send(&mut self.spi_bus, hif_header, &mut [], &mut [])?;
         Ok(())
     }
+
+    /// Erases every network the Atwinc1500 has saved
+    /// to flash, so connect_default_network has nothing
+    /// to connect to afterwards
+    ///
+    /// This requires firmware 19.6.1 or newer,
+    /// older firmware returns UnsupportedFirmware
+    pub fn erase_saved_credentials(&mut self) -> Result<(), Error> {
+        const ALL_NETWORKS: u8 = 0xff;
+        if !self.supported_features().erase_saved_credentials {
+            return Err(Error::UnsupportedFirmware);
+        }
+        // ssid length then ssid (34 bytes), padding (2 bytes).
+        // A length of 0xff selects every saved network
+        let mut data: [u8; 36] = [0; 36];
+        data[0] = ALL_NETWORKS;
+        let hif_header = HifHeader::new(
+            group_ids::WIFI,
+            commands::wifi::REQ_DELETE_APID,
+            data.len() as u16,
+        );
+        self.hif
+            .send(&mut self.spi_bus, hif_header, &mut data, &mut [])?;
+        Ok(())
+    }
 }
 
+#[cfg(feature = "nal")]
 impl<SPI, D, O, I> TcpClientStack for Atwinc1500<SPI, D, O, I>
 where
     SPI: Transfer<u8>,
@@ -300,7 +1862,7 @@ where
     type Error = Error;
 
     fn socket(&mut self) -> Result<TcpSocket, Error> {
-        todo!()
+        self.tcp_socket()
     }
 
     fn connect(
@@ -308,34 +1870,35 @@ where
         _socket: &mut TcpSocket,
         _address: SocketAddr,
     ) -> Result<(), embedded_nal::nb::Error<Error>> {
-        todo!()
+        Err(embedded_nal::nb::Error::Other(Error::NotImplemented))
     }
 
     fn is_connected(&mut self, _socket: &TcpSocket) -> Result<bool, Error> {
-        todo!()
+        Err(Error::NotImplemented)
     }
 
     fn send(
         &mut self,
-        _socket: &mut TcpSocket,
-        _data: &[u8],
+        socket: &mut TcpSocket,
+        data: &[u8],
     ) -> Result<usize, embedded_nal::nb::Error<Error>> {
-        todo!()
+        would_block(self.tcp_send(socket, data))
     }
 
     fn receive(
         &mut self,
-        _socket: &mut TcpSocket,
-        _data: &mut [u8],
+        socket: &mut TcpSocket,
+        data: &mut [u8],
     ) -> Result<usize, embedded_nal::nb::Error<Error>> {
-        todo!()
+        would_block(self.tcp_receive(socket, data))
     }
 
-    fn close(&mut self, _socket: TcpSocket) -> Result<(), Error> {
-        todo!()
+    fn close(&mut self, socket: TcpSocket) -> Result<(), Error> {
+        self.close_socket(socket)
     }
 }
 
+#[cfg(feature = "nal")]
 impl<SPI, D, O, I> TcpFullStack for Atwinc1500<SPI, D, O, I>
 where
     SPI: Transfer<u8>,
@@ -343,18 +1906,64 @@ where
     O: OutputPin,
     I: InputPin,
 {
-    fn bind(&mut self, _socket: &mut TcpSocket, _port: u16) -> Result<(), Error> {
-        todo!()
+    fn bind(&mut self, socket: &mut TcpSocket, port: u16) -> Result<(), Error> {
+        self.tcp_bind(socket, port)
     }
 
-    fn listen(&mut self, _socket: &mut TcpSocket) -> Result<(), Error> {
-        todo!()
+    fn listen(&mut self, socket: &mut TcpSocket) -> Result<(), Error> {
+        self.tcp_listen(socket)
     }
 
     fn accept(
         &mut self,
-        _socket: &mut TcpSocket,
+        socket: &mut TcpSocket,
     ) -> Result<(TcpSocket, SocketAddr), embedded_nal::nb::Error<Error>> {
-        todo!()
+        let (accepted, peer) = would_block(self.tcp_accept(socket))?;
+        let ip = embedded_nal::Ipv4Addr::from(peer.ip().octets());
+        let peer = SocketAddr::V4(embedded_nal::SocketAddrV4::new(ip, peer.port()));
+        Ok((accepted, peer))
+    }
+}
+
+#[cfg(feature = "nal")]
+impl<SPI, D, O, I> Dns for Atwinc1500<SPI, D, O, I>
+where
+    SPI: Transfer<u8>,
+    D: DelayMs<u32>,
+    O: OutputPin,
+    I: InputPin,
+{
+    type Error = Error;
+
+    fn get_host_by_name(
+        &mut self,
+        hostname: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, embedded_nal::nb::Error<Error>> {
+        // The Atwinc1500 only resolves ipv4 addresses
+        if let AddrType::IPv6 = addr_type {
+            return Err(embedded_nal::nb::Error::Other(Error::NotImplemented));
+        }
+        let ip = would_block(self.resolve_host(hostname))?;
+        Ok(IpAddr::V4(embedded_nal::Ipv4Addr::from(ip.octets())))
+    }
+
+    fn get_host_by_address(
+        &mut self,
+        _addr: IpAddr,
+    ) -> Result<heapless::String<256>, embedded_nal::nb::Error<Error>> {
+        // The firmware has no reverse lookup
+        Err(embedded_nal::nb::Error::Other(Error::UnsupportedFirmware))
+    }
+}
+
+/// Converts the result of a driver method that
+/// returns Ok(None) when it would block
+#[cfg(feature = "nal")]
+fn would_block<T>(result: Result<Option<T>, Error>) -> Result<T, embedded_nal::nb::Error<Error>> {
+    match result {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(embedded_nal::nb::Error::WouldBlock),
+        Err(e) => Err(embedded_nal::nb::Error::Other(e)),
     }
 }
@@ -0,0 +1,505 @@
+//! Simulated Atwinc1500 for testing without hardware
+//!
+//! MockAtwinc emulates the chip at the spi protocol level. It keeps
+//! a register file and dma memory, follows the host interface
+//! handshake for frames sent by the driver, and hands scripted
+//! frames to handle_events as if the chip had sent them.
+extern crate std;
+
+use crate::error::Error;
+use crate::registers;
+use crate::spi::commands;
+use crate::types::{ChipSelect, InitConfig, Reset, Wake};
+use crate::Atwinc1500;
+use core::convert::Infallible;
+use embedded_hal::blocking::{delay::DelayMs, spi::Transfer};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Address the mock allocates for frames sent by the driver
+pub const TX_ADDRESS: u32 = 0x40000;
+/// Address the mock places frames it sends to the driver
+pub const RX_ADDRESS: u32 = 0x50000;
+
+const HIF_HEADER_SIZE: usize = 8;
+const FINISH_INIT_VAL: u32 = 0x02532636;
+
+/// The driver type produced by MockAtwinc::driver
+pub type MockDriver = Atwinc1500<MockSpi, MockDelay, MockPin, MockPin>;
+
+/// A frame the driver sent to the mock
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SentFrame {
+    /// Group id
+    pub gid: u8,
+    /// Opcode
+    pub op: u8,
+    /// Everything after the hif header
+    pub data: Vec<u8>,
+}
+
+/// What the mock expects the next spi transfer to be
+#[derive(Copy, Clone)]
+enum Phase {
+    Command,
+    DmaReadResponse { address: u32 },
+    DmaReadData { address: u32 },
+    DmaWriteResponse { address: u32 },
+    DmaWriteMark { address: u32 },
+    DmaWriteData { address: u32 },
+    DmaWriteAck,
+}
+
+struct MockChip {
+    registers: HashMap<u32, u32>,
+    memory: HashMap<u32, u8>,
+    crc_enabled: bool,
+    phase: Phase,
+    rx_frames: VecDeque<Vec<u8>>,
+    // Frames queued once a request with the opcode is sent
+    replies: VecDeque<(u8, Vec<u8>)>,
+    rx_active: bool,
+    rx_irq: bool,
+    sent_frames: Vec<SentFrame>,
+    elapsed_ms: u32,
+    firmware_starts: bool,
+    flash_id: u32,
+    flash_result: Option<u32>,
+    failed_allocations: u32,
+}
+
+impl MockChip {
+    fn new() -> Self {
+        let mut registers = HashMap::new();
+        registers.insert(registers::EFUSE_REG, 0x80000000);
+        registers.insert(registers::M2M_WAIT_FOR_HOST_REG, 1);
+        // firmware 19.6.1 needing driver 19.3.0
+        registers.insert(registers::NMI_REV_REG, 0x13301361);
+        Self {
+            registers,
+            memory: HashMap::new(),
+            crc_enabled: true,
+            phase: Phase::Command,
+            rx_frames: VecDeque::new(),
+            replies: VecDeque::new(),
+            rx_active: false,
+            rx_irq: false,
+            sent_frames: Vec::new(),
+            elapsed_ms: 0,
+            firmware_starts: true,
+            // 8 megabit Winbond flash
+            flash_id: 0x001440ef,
+            flash_result: None,
+            failed_allocations: 0,
+        }
+    }
+
+    fn transfer(&mut self, words: &mut [u8]) {
+        match self.phase {
+            Phase::Command => self.command(words),
+            Phase::DmaReadResponse { address } => {
+                respond(words, 0, &[commands::CMD_DMA_EXT_READ, 0, 0xf3]);
+                self.phase = Phase::DmaReadData { address };
+            }
+            Phase::DmaReadData { address } => {
+                for (i, word) in words.iter_mut().enumerate() {
+                    *word = self.read_memory(address + i as u32);
+                }
+                self.phase = Phase::Command;
+            }
+            Phase::DmaWriteResponse { address } => {
+                respond(words, 0, &[commands::CMD_DMA_EXT_WRITE, 0]);
+                self.phase = Phase::DmaWriteMark { address };
+            }
+            Phase::DmaWriteMark { address } => {
+                self.phase = Phase::DmaWriteData { address };
+            }
+            Phase::DmaWriteData { address } => {
+                for (i, word) in words.iter().enumerate() {
+                    self.memory.insert(address + i as u32, *word);
+                }
+                self.phase = Phase::DmaWriteAck;
+            }
+            Phase::DmaWriteAck => {
+                respond(words, 0, &[0xc3]);
+                self.phase = Phase::Command;
+            }
+        }
+    }
+
+    fn command(&mut self, words: &mut [u8]) {
+        let crc: usize = self.crc_enabled as usize;
+        let cmd = words[0];
+        match cmd {
+            commands::CMD_SINGLE_READ | commands::CMD_INTERNAL_READ => {
+                let address = match cmd {
+                    commands::CMD_SINGLE_READ => be_bytes(&words[1..4]),
+                    _ => be_bytes(&[words[1] & 0x7f, words[2]]),
+                };
+                let value = self.read_register(address).to_le_bytes();
+                let response = [cmd, 0, 0xf3, value[0], value[1], value[2], value[3]];
+                respond(words, 4 + crc, &response);
+            }
+            commands::CMD_SINGLE_WRITE | commands::CMD_INTERNAL_WRITE => {
+                let (address, data) = match cmd {
+                    commands::CMD_SINGLE_WRITE => (be_bytes(&words[1..4]), be_bytes(&words[4..8])),
+                    _ => (
+                        be_bytes(&[words[1] & 0x7f, words[2]]),
+                        be_bytes(&words[3..7]),
+                    ),
+                };
+                // The crc setting changes after the response
+                respond(words, 8 + crc, &[cmd, 0]);
+                self.write_register(address, data);
+            }
+            commands::CMD_DMA_EXT_READ => {
+                let address = be_bytes(&words[1..4]);
+                self.phase = Phase::DmaReadResponse { address };
+            }
+            commands::CMD_DMA_EXT_WRITE => {
+                let address = be_bytes(&words[1..4]);
+                self.phase = Phase::DmaWriteResponse { address };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_register(&self, address: u32) -> u32 {
+        match address {
+            registers::WIFI_HOST_RCV_CTRL_0 => match self.rx_frames.front() {
+                Some(frame) if self.rx_active => ((frame.len() as u32) << 2) | self.rx_irq as u32,
+                _ => 0,
+            },
+            // Also DUMMY_REGISTER, holding a flash id after a read id
+            registers::WIFI_HOST_RCV_CTRL_1 => self.flash_result.unwrap_or(RX_ADDRESS),
+            registers::WIFI_HOST_RCV_CTRL_4 => TX_ADDRESS,
+            _ => *self.registers.get(&address).unwrap_or(&0),
+        }
+    }
+
+    fn write_register(&mut self, address: u32, value: u32) {
+        match address {
+            registers::NMI_SPI_PROTOCOL_CONFIG => {
+                self.crc_enabled = value & 0xc != 0;
+                self.registers.insert(address, value);
+            }
+            registers::rNMI_GLB_RESET => {
+                self.registers.insert(address, value);
+                if value == 0 {
+                    self.reset();
+                }
+            }
+            registers::SPI_FLASH_CMD_CNT => {
+                // Only the read id command is simulated
+                if self.registers.get(&registers::SPI_FLASH_BUF1) == Some(&0x9f) {
+                    self.flash_result = Some(self.flash_id);
+                    self.registers.insert(registers::SPI_FLASH_TR_DONE, 1);
+                }
+            }
+            registers::WIFI_HOST_RCV_CTRL_0 => {
+                if value & 0x1 == 0 {
+                    self.rx_irq = false;
+                }
+                if value & 0x2 != 0 && self.rx_active {
+                    self.rx_frames.pop_front();
+                    self.rx_active = false;
+                    self.load_frame();
+                }
+            }
+            registers::WIFI_HOST_RCV_CTRL_2 => {
+                // The buffer is allocated immediately
+                // unless a failure was requested
+                if value & 0x2 != 0 && self.failed_allocations > 0 {
+                    self.failed_allocations -= 1;
+                    self.registers.insert(address, value);
+                } else {
+                    self.registers.insert(address, value & !0x2);
+                }
+            }
+            registers::WIFI_HOST_RCV_CTRL_3 => {
+                if value & 0x2 != 0 {
+                    self.record_frame(value >> 2);
+                }
+            }
+            registers::BOOTROM_REG
+                if value == registers::M2M_START_FIRMWARE && self.firmware_starts =>
+            {
+                self.registers
+                    .insert(registers::NMI_STATE_REG, FINISH_INIT_VAL);
+            }
+            _ => {
+                self.registers.insert(address, value);
+            }
+        }
+    }
+
+    fn read_memory(&self, address: u32) -> u8 {
+        *self.memory.get(&address).unwrap_or(&0)
+    }
+
+    fn record_frame(&mut self, address: u32) {
+        let length =
+            self.read_memory(address + 2) as u32 | (self.read_memory(address + 3) as u32) << 8;
+        let data = (address + HIF_HEADER_SIZE as u32..address + length)
+            .map(|a| self.read_memory(a))
+            .collect();
+        let op = self.read_memory(address + 1);
+        self.sent_frames.push(SentFrame {
+            gid: self.read_memory(address),
+            op,
+            data,
+        });
+        if matches!(self.replies.front(), Some((request, _)) if *request == op) {
+            if let Some((_, frame)) = self.replies.pop_front() {
+                self.rx_frames.push_back(frame);
+                self.load_frame();
+            }
+        }
+    }
+
+    /// Returns to the state after power on. Firmware
+    /// settings and what has been recorded are kept
+    fn reset(&mut self) {
+        self.registers.remove(&registers::NMI_STATE_REG);
+        self.registers.remove(&registers::NMI_SPI_PROTOCOL_CONFIG);
+        self.crc_enabled = true;
+        self.phase = Phase::Command;
+        self.rx_frames.clear();
+        self.rx_active = false;
+        self.rx_irq = false;
+        self.flash_result = None;
+    }
+
+    /// Places the next queued frame in memory
+    /// and raises the receive interrupt
+    fn load_frame(&mut self) {
+        if self.rx_active {
+            return;
+        }
+        if let Some(frame) = self.rx_frames.front() {
+            for (i, byte) in frame.iter().enumerate() {
+                self.memory.insert(RX_ADDRESS + i as u32, *byte);
+            }
+            self.rx_active = true;
+            self.rx_irq = true;
+        }
+    }
+}
+
+/// Builds a frame with its hif header
+fn frame(gid: u8, op: u8, payload: &[u8]) -> Vec<u8> {
+    let length = (HIF_HEADER_SIZE + payload.len()) as u16;
+    let mut frame = Vec::with_capacity(length as usize);
+    frame.extend_from_slice(&[gid, op, length as u8, (length >> 8) as u8, 0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Copies a response into words starting at index,
+/// dropping anything past the end of words
+fn respond(words: &mut [u8], index: usize, response: &[u8]) {
+    for (word, byte) in words.iter_mut().skip(index).zip(response) {
+        *word = *byte;
+    }
+}
+
+/// Combines big endian bytes into a u32
+fn be_bytes(bytes: &[u8]) -> u32 {
+    combine_bytes!(bytes)
+}
+
+/// Handle to a simulated Atwinc1500
+///
+/// Clones share the same simulated chip, so a test can keep
+/// a MockAtwinc to script frames and inspect what was sent
+/// after handing the driver its spi bus
+#[derive(Clone)]
+pub struct MockAtwinc {
+    chip: Rc<RefCell<MockChip>>,
+}
+
+impl Default for MockAtwinc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockAtwinc {
+    /// Creates a simulated Atwinc1500 that
+    /// boots straight into its firmware
+    pub fn new() -> Self {
+        Self {
+            chip: Rc::new(RefCell::new(MockChip::new())),
+        }
+    }
+
+    /// Creates a driver connected to this mock.
+    /// The driver is initialized against the mock
+    /// the same way it would be against a real chip
+    pub fn driver(&self, crc: bool) -> Result<MockDriver, Error> {
+        self.driver_with_config(crc, InitConfig::default())
+    }
+
+    /// Creates a driver connected to this mock
+    /// using new_with_config
+    pub fn driver_with_config(&self, crc: bool, config: InitConfig) -> Result<MockDriver, Error> {
+        Atwinc1500::new_with_config(
+            self.spi(),
+            MockDelay {
+                chip: self.chip.clone(),
+            },
+            ChipSelect(MockPin::default()),
+            MockPin::default(),
+            Reset(MockPin {
+                reset: Some(self.chip.clone()),
+            }),
+            Wake(MockPin::default()),
+            crc,
+            config,
+        )
+    }
+
+    /// Gets an spi bus connected to this mock
+    pub fn spi(&self) -> MockSpi {
+        MockSpi {
+            chip: self.chip.clone(),
+        }
+    }
+
+    /// Queues a frame for the driver to receive.
+    /// The hif header is added to payload
+    pub fn push_frame(&self, gid: u8, op: u8, payload: &[u8]) {
+        let mut chip = self.chip.borrow_mut();
+        chip.rx_frames.push_back(frame(gid, op, payload));
+        chip.load_frame();
+    }
+
+    /// Queues bytes as a frame without building its hif
+    /// header, for simulating a corrupted header
+    pub fn push_raw_frame(&self, frame: &[u8]) {
+        let mut chip = self.chip.borrow_mut();
+        chip.rx_frames.push_back(frame.to_vec());
+        chip.load_frame();
+    }
+
+    /// Queues a frame that is only sent once the driver
+    /// sends a request with the opcode request. Replies
+    /// are matched in the order they were pushed, so a
+    /// conversation can be scripted ahead of time
+    pub fn push_reply(&self, request: u8, gid: u8, op: u8, payload: &[u8]) {
+        self.chip
+            .borrow_mut()
+            .replies
+            .push_back((request, frame(gid, op, payload)));
+    }
+
+    /// Returns true while a queued frame
+    /// has not been fully received
+    pub fn has_pending_frames(&self) -> bool {
+        !self.chip.borrow().rx_frames.is_empty()
+    }
+
+    /// Removes and returns the frames
+    /// the driver has sent so far
+    pub fn take_sent_frames(&self) -> Vec<SentFrame> {
+        core::mem::take(&mut self.chip.borrow_mut().sent_frames)
+    }
+
+    /// Sets the value of a register
+    pub fn set_register(&self, address: u32, value: u32) {
+        self.chip.borrow_mut().registers.insert(address, value);
+    }
+
+    /// Gets the value of a register
+    pub fn register(&self, address: u32) -> u32 {
+        self.chip.borrow().read_register(address)
+    }
+
+    /// Sets whether the firmware starts when the
+    /// driver writes M2M_START_FIRMWARE. Defaults to true
+    pub fn set_firmware_starts(&self, starts: bool) {
+        self.chip.borrow_mut().firmware_starts = starts;
+    }
+
+    /// Sets the jedec id returned by the flash.
+    /// Defaults to an 8 megabit flash
+    pub fn set_flash_id(&self, id: u32) {
+        self.chip.borrow_mut().flash_id = id;
+    }
+
+    /// Makes the next count buffer allocations
+    /// fail as if the Atwinc1500 had none free
+    pub fn fail_allocations(&self, count: u32) {
+        self.chip.borrow_mut().failed_allocations = count;
+    }
+
+    /// Total milliseconds the driver has delayed
+    pub fn elapsed_ms(&self) -> u32 {
+        self.chip.borrow().elapsed_ms
+    }
+}
+
+/// Spi bus connected to a MockAtwinc
+pub struct MockSpi {
+    chip: Rc<RefCell<MockChip>>,
+}
+
+impl Transfer<u8> for MockSpi {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.chip.borrow_mut().transfer(words);
+        Ok(words)
+    }
+}
+
+/// Delay that records time spent
+/// instead of waiting
+pub struct MockDelay {
+    chip: Rc<RefCell<MockChip>>,
+}
+
+impl DelayMs<u32> for MockDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.chip.borrow_mut().elapsed_ms += ms;
+    }
+}
+
+/// Pin that accepts every state change
+/// and always reads high. The reset pin
+/// given to the driver resets the mock
+/// when it is pulled low
+#[derive(Default)]
+pub struct MockPin {
+    reset: Option<Rc<RefCell<MockChip>>>,
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        if let Some(chip) = &self.reset {
+            chip.borrow_mut().reset();
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
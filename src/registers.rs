@@ -30,7 +30,23 @@ pub const rNMI_GP_REG_0: u32 = 0x149c;
 pub const rNMI_GLB_RESET: u32 = 0x1400;
 #[allow(non_upper_case_globals)]
 pub const rNMI_BOOT_RESET_MUX: u32 = 0x1118;
+// A set bit disables the pull up
+pub const PULLUP_CTRL_REG: u32 = 0x142c;
+pub const GPIO_VAL_REG: u32 = 0x20100;
+// Level of every gpio pin
+pub const GPIO_GET_VAL_REG: u32 = 0x20104;
+pub const GPIO_DIR_REG: u32 = 0x20108;
 pub const NMI_REV_REG: u32 = 0x207ac;
+// Controller for the spi flash inside the Atwinc1500
+pub const SPI_FLASH_CMD_CNT: u32 = 0x10204;
+pub const SPI_FLASH_DATA_CNT: u32 = 0x10208;
+pub const SPI_FLASH_BUF1: u32 = 0x1020c;
+pub const SPI_FLASH_BUF_DIR: u32 = 0x10214;
+pub const SPI_FLASH_TR_DONE: u32 = 0x10218;
+pub const SPI_FLASH_DMA_ADDR: u32 = 0x1021c;
+// Flash commands write their result here. It
+// is the same address as WIFI_HOST_RCV_CTRL_1
+pub const DUMMY_REGISTER: u32 = 0x1084;
 pub const NMI_REV_REG_ATE: u32 = 0x1048;
 pub const M2M_FINISH_INIT_STATE: u32 = 0x02532636;
 pub const M2M_FINISH_BOOT_ROM: u32 = 0x10add09e;
@@ -1,23 +1,500 @@
 //! Socket related members
+use crate::error::Error;
+use crate::event::Event;
+use core::net::{Ipv4Addr, SocketAddrV4};
 
-/// Bind command
-pub const BIND: u8 = 65;
-/// Listen command
-pub const LISTEN: u8 = 66;
-/// Accept command
-pub const ACCEPT: u8 = 67;
-/// Connect command
-pub const CONNECT: u8 = 68;
-/// Send command
-pub const SEND: u8 = 69;
-/// Receive command
-pub const RECV: u8 = 70;
-/// Send To command
-pub const SENDTO: u8 = 71;
-/// Receive from command
-pub const RECVFROM: u8 = 72;
-/// Close command
-pub const CLOSE: u8 = 73;
+/// Number of tcp sockets in the Atmel firmware
+/// build, with handles 0 through 6
+pub const TCP_SOCK_MAX: usize = 7;
+/// Number of udp sockets in the Atmel firmware
+/// build, with handles following the tcp sockets
+pub const UDP_SOCK_MAX: usize = 4;
+/// Number of sockets the firmware has. Socket
+/// handles from the Atwinc1500 are below this.
+/// A firmware built with a different socket
+/// configuration needs these constants changed
+pub const MAX_SOCKETS: usize = TCP_SOCK_MAX + UDP_SOCK_MAX;
+
+/// Size of a socket address in a socket frame
+pub const SOCKADDR_SIZE: usize = 8;
+/// Address family of ipv4 socket addresses
+const AF_INET: u16 = 2;
+
+/// Opcodes of socket frames. Most are used both
+/// to send a request and for the Atwinc1500's
+/// response, use is_request and is_response
+/// to tell them apart
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SocketCommand {
+    /// Bind command
+    Bind = 65,
+    /// Listen command
+    Listen = 66,
+    /// Accept response
+    Accept = 67,
+    /// Connect command
+    Connect = 68,
+    /// Send command
+    Send = 69,
+    /// Receive command
+    Recv = 70,
+    /// Send To command
+    SendTo = 71,
+    /// Receive from command
+    RecvFrom = 72,
+    /// Close command
+    Close = 73,
+    /// Dns resolve command
+    DnsResolve = 74,
+    /// Ssl connect command
+    SslConnect = 75,
+    /// Ssl send command
+    SslSend = 76,
+    /// Ssl receive command
+    SslRecv = 77,
+    /// Ssl close command
+    SslClose = 78,
+    /// Set socket option command. The firmware's options
+    /// are the udp send callback, multicast membership and
+    /// tcp keepalive. There are no buffer thresholds, a
+    /// socket is readable as soon as any data arrives
+    SetSocketOption = 79,
+    /// Ssl create command
+    SslCreate = 80,
+    /// Ssl set socket option command
+    SslSetSockOpt = 81,
+    /// Ping command
+    Ping = 82,
+    /// Ssl set cipher suite list command
+    SslSetCsList = 83,
+    /// Ssl bind command
+    SslBind = 84,
+    /// Ssl certificate expiration check command
+    SslExpCheck = 85,
+}
+
+impl SocketCommand {
+    /// Returns true if the host can send this
+    /// opcode to the Atwinc1500
+    pub fn is_request(&self) -> bool {
+        !matches!(self, SocketCommand::Accept)
+    }
+
+    /// Returns true if the Atwinc1500 sends
+    /// this opcode back to the host
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            SocketCommand::Bind
+                | SocketCommand::Listen
+                | SocketCommand::Accept
+                | SocketCommand::Connect
+                | SocketCommand::Send
+                | SocketCommand::Recv
+                | SocketCommand::SendTo
+                | SocketCommand::RecvFrom
+                | SocketCommand::DnsResolve
+                | SocketCommand::SslConnect
+                | SocketCommand::SslSend
+                | SocketCommand::SslRecv
+                | SocketCommand::Ping
+        )
+    }
+}
+
+impl TryFrom<u8> for SocketCommand {
+    type Error = Error;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        Ok(match val {
+            65 => SocketCommand::Bind,
+            66 => SocketCommand::Listen,
+            67 => SocketCommand::Accept,
+            68 => SocketCommand::Connect,
+            69 => SocketCommand::Send,
+            70 => SocketCommand::Recv,
+            71 => SocketCommand::SendTo,
+            72 => SocketCommand::RecvFrom,
+            73 => SocketCommand::Close,
+            74 => SocketCommand::DnsResolve,
+            75 => SocketCommand::SslConnect,
+            76 => SocketCommand::SslSend,
+            77 => SocketCommand::SslRecv,
+            78 => SocketCommand::SslClose,
+            79 => SocketCommand::SetSocketOption,
+            80 => SocketCommand::SslCreate,
+            81 => SocketCommand::SslSetSockOpt,
+            82 => SocketCommand::Ping,
+            83 => SocketCommand::SslSetCsList,
+            84 => SocketCommand::SslBind,
+            85 => SocketCommand::SslExpCheck,
+            _ => return Err(Error::InvalidSocketCommand),
+        })
+    }
+}
+
+/// Socket options sent with SetSocketOption
+pub(crate) mod options {
+    pub const SO_TCP_KEEPALIVE: u8 = 4;
+    pub const SO_TCP_KEEPIDLE: u8 = 5;
+    pub const SO_TCP_KEEPINTVL: u8 = 6;
+}
+
+/// Ssl socket options sent with SslSetSockOpt
+pub(crate) mod ssl_options {
+    pub const SO_SSL_SNI: u8 = 2;
+}
+
+/// Flags sent with SslConnect
+pub(crate) mod ssl_flags {
+    pub const SSL_FLAGS_ACTIVE: u8 = 0x01;
+    pub const SSL_FLAGS_NO_TX_COPY: u8 = 0x20;
+}
+
+/// Tls cipher suites the Atwinc1500 firmware
+/// supports, with their bit in the list sent
+/// by set_cipher_suites. The ecdhe suites also
+/// need the host to do the ecc operations, which
+/// this driver doesn't implement yet
+#[repr(u32)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CipherSuite {
+    /// TLS_RSA_WITH_AES_128_CBC_SHA
+    RsaWithAes128CbcSha = 1 << 0,
+    /// TLS_RSA_WITH_AES_128_CBC_SHA256
+    RsaWithAes128CbcSha256 = 1 << 1,
+    /// TLS_DHE_RSA_WITH_AES_128_CBC_SHA
+    DheRsaWithAes128CbcSha = 1 << 2,
+    /// TLS_DHE_RSA_WITH_AES_128_CBC_SHA256
+    DheRsaWithAes128CbcSha256 = 1 << 3,
+    /// TLS_RSA_WITH_AES_128_GCM_SHA256
+    RsaWithAes128GcmSha256 = 1 << 4,
+    /// TLS_DHE_RSA_WITH_AES_128_GCM_SHA256
+    DheRsaWithAes128GcmSha256 = 1 << 5,
+    /// TLS_RSA_WITH_AES_256_CBC_SHA
+    RsaWithAes256CbcSha = 1 << 6,
+    /// TLS_RSA_WITH_AES_256_CBC_SHA256
+    RsaWithAes256CbcSha256 = 1 << 7,
+    /// TLS_DHE_RSA_WITH_AES_256_CBC_SHA
+    DheRsaWithAes256CbcSha = 1 << 8,
+    /// TLS_DHE_RSA_WITH_AES_256_CBC_SHA256
+    DheRsaWithAes256CbcSha256 = 1 << 9,
+    /// TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA
+    EcdheRsaWithAes128CbcSha = 1 << 10,
+    /// TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA
+    EcdheRsaWithAes256CbcSha = 1 << 11,
+    /// TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256
+    EcdheRsaWithAes128CbcSha256 = 1 << 12,
+    /// TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256
+    EcdheEcdsaWithAes128CbcSha256 = 1 << 13,
+    /// TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+    EcdheRsaWithAes128GcmSha256 = 1 << 14,
+    /// TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+    EcdheEcdsaWithAes128GcmSha256 = 1 << 15,
+}
+
+/// Size of the value in an ssl socket option
+pub(crate) const SSL_MAX_OPT_LEN: usize = 64;
+
+/// Keepalive times are sent to the
+/// firmware in units of 500ms
+pub(crate) const KEEPALIVE_UNIT_MS: u32 = 500;
+/// Longest idle time before the first keepalive probe
+pub const MAX_KEEPALIVE_IDLE_MS: u32 = u16::MAX as u32 * KEEPALIVE_UNIT_MS;
+/// Longest time between keepalive probes
+pub const MAX_KEEPALIVE_INTERVAL_MS: u32 = u8::MAX as u32 * KEEPALIVE_UNIT_MS;
+
+/// Socket error sent when a receive times out
+pub(crate) const SOCK_ERR_TIMEOUT: i16 = -13;
+
+/// Ping error code when the host replied
+pub const PING_ERR_SUCCESS: u8 = 0;
+/// Ping error code when the host can't be reached
+pub const PING_ERR_DEST_UNREACH: u8 = 1;
+/// Ping error code when no reply arrived
+pub const PING_ERR_TIMEOUT: u8 = 2;
+/// Size of a ping response sent by the Atwinc1500
+pub(crate) const PING_REPLY_SIZE: usize = 20;
+
+/// Result of a ping sent with request_ping
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PingResult {
+    /// Round trip time in milliseconds
+    pub rtt_ms: u32,
+    /// PING_ERR_SUCCESS, PING_ERR_DEST_UNREACH
+    /// or PING_ERR_TIMEOUT
+    pub error: u8,
+}
+
+/// Largest payload the Atwinc1500 accepts in
+/// one send, longer sends are split
+pub const SOCKET_BUFFER_MAX_LENGTH: usize = 1400;
+/// Bytes received on a tcp socket that the
+/// driver holds until they are read. The reply to
+/// a receive request carries a whole segment, up
+/// to SOCKET_BUFFER_MAX_LENGTH bytes
+pub const SOCKET_RX_BUFFER_SIZE: usize = SOCKET_BUFFER_MAX_LENGTH;
+/// Where the payload of a tcp send starts,
+/// counted from the end of the hif header
+pub(crate) const TCP_TX_PACKET_OFFSET: usize = 80;
+/// Where the payload of a tls send starts until the
+/// Atwinc1500 reports it with the connect response,
+/// leaving room for the 5 byte tls record header
+pub(crate) const SSL_TX_PACKET_OFFSET: usize = TCP_TX_PACKET_OFFSET + 5;
+
+/// Ring buffer of the bytes received on a socket
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RxBuffer {
+    data: [u8; SOCKET_RX_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl RxBuffer {
+    /// Creates an empty RxBuffer
+    pub fn new() -> Self {
+        Self {
+            data: [0; SOCKET_RX_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns true if there is nothing to read
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the number of bytes that can still be written
+    pub fn free(&self) -> usize {
+        SOCKET_RX_BUFFER_SIZE - self.len
+    }
+
+    /// Number of bytes waiting to be read
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Gets the free space as two slices, in the
+    /// order they are written. Call commit with
+    /// the number of bytes written to them
+    pub fn free_slices(&mut self) -> (&mut [u8], &mut [u8]) {
+        if self.len == SOCKET_RX_BUFFER_SIZE {
+            return (&mut [], &mut []);
+        }
+        let end = (self.start + self.len) % SOCKET_RX_BUFFER_SIZE;
+        if end >= self.start {
+            let (head, tail) = self.data.split_at_mut(end);
+            (tail, &mut head[..self.start])
+        } else {
+            (&mut self.data[end..self.start], &mut [])
+        }
+    }
+
+    /// Adds count bytes written to the free slices
+    pub fn commit(&mut self, count: usize) {
+        self.len += count.min(self.free());
+    }
+
+    /// Moves as many bytes as fit into buffer
+    /// and returns how many were moved
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len().min(self.len);
+        for (i, byte) in buffer[..count].iter_mut().enumerate() {
+            *byte = self.data[(self.start + i) % SOCKET_RX_BUFFER_SIZE];
+        }
+        self.start = (self.start + count) % SOCKET_RX_BUFFER_SIZE;
+        self.len -= count;
+        count
+    }
+}
+
+/// Host side state of an open socket. Socket
+/// handles are given out by the host, the
+/// Atwinc1500 learns of a socket from the
+/// first request that uses it
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SocketState {
+    /// Sent with requests so the firmware can tell
+    /// a reused handle apart from the socket
+    /// that had it before
+    pub session: u16,
+    /// Received bytes not read yet
+    pub rx: RxBuffer,
+    /// A receive request is waiting on data
+    pub recv_pending: bool,
+    /// The peer closed the socket or it failed
+    pub closed: bool,
+    /// Received bytes were dropped because
+    /// rx was full
+    pub overflow: bool,
+    /// Handle of the listening socket that accepted
+    /// this connection, until accept returns it
+    pub accepted_by: Option<u8>,
+    /// Address of the peer of an accepted connection
+    pub peer: Option<SocketAddrV4>,
+    /// A connect request is waiting on its response
+    pub connecting: bool,
+    /// The socket connected to its peer
+    pub connected: bool,
+    /// The tls handshake of the connect failed
+    pub handshake_failed: bool,
+    /// Where the payload of a send starts
+    pub tx_offset: usize,
+}
+
+impl SocketState {
+    /// Creates the state of a newly opened socket
+    pub fn new(session: u16) -> Self {
+        Self {
+            session,
+            rx: RxBuffer::new(),
+            recv_pending: false,
+            closed: false,
+            overflow: false,
+            accepted_by: None,
+            peer: None,
+            connecting: false,
+            connected: false,
+            handshake_failed: false,
+            tx_offset: TCP_TX_PACKET_OFFSET,
+        }
+    }
+}
 
 /// TcpSocket implementation
-pub struct TcpSocket {}
+#[derive(Debug)]
+pub struct TcpSocket {
+    pub(crate) handle: u8,
+    /// Set by connect_tls so sends, receives and the
+    /// close use the ssl socket commands
+    pub(crate) tls: bool,
+}
+
+impl TcpSocket {
+    /// Gets the handle the Atwinc1500
+    /// assigned to this socket
+    pub fn handle(&self) -> u8 {
+        self.handle
+    }
+
+    /// Returns true if the socket was
+    /// opened with connect_tls
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Swaps a tcp command for its ssl
+    /// variant on a tls socket
+    pub(crate) fn command(&self, command: SocketCommand) -> SocketCommand {
+        match (self.tls, command) {
+            (true, SocketCommand::Send) => SocketCommand::SslSend,
+            (true, SocketCommand::Recv) => SocketCommand::SslRecv,
+            (true, SocketCommand::Close) => SocketCommand::SslClose,
+            _ => command,
+        }
+    }
+}
+
+/// Which sockets are readable, writable or closed,
+/// kept up to date from the socket events handled
+/// by handle_events. A socket stays readable until
+/// its data is received and writable until it is
+/// closed, like select
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+pub struct SocketReadiness {
+    readable: u16,
+    writable: u16,
+    closed: u16,
+}
+
+impl SocketReadiness {
+    /// Returns true if the socket received data, or
+    /// has a connection to accept if it is listening
+    pub fn is_readable(&self, socket: &TcpSocket) -> bool {
+        self.readable & (1 << socket.handle) != 0
+    }
+
+    /// Returns true if the socket connected
+    /// or finished sending
+    pub fn is_writable(&self, socket: &TcpSocket) -> bool {
+        self.writable & (1 << socket.handle) != 0
+    }
+
+    /// Returns true if the socket was closed by
+    /// the peer or failed with an error
+    pub fn is_closed(&self, socket: &TcpSocket) -> bool {
+        self.closed & (1 << socket.handle) != 0
+    }
+
+    /// Returns true if no socket is ready
+    pub fn is_empty(&self) -> bool {
+        self.readable | self.writable | self.closed == 0
+    }
+
+    /// Updates the readiness from a socket event
+    pub(crate) fn update(&mut self, event: Event) {
+        match event {
+            Event::SocketReadable { handle } => self.readable |= 1 << handle,
+            Event::SocketWritable { handle } => self.writable |= 1 << handle,
+            Event::SocketClosed { handle } => {
+                self.clear(handle);
+                self.closed |= 1 << handle;
+            }
+            _ => {}
+        }
+    }
+
+    /// Clears the readable flag of a socket
+    /// once its data has been read
+    pub(crate) fn clear_readable(&mut self, handle: u8) {
+        self.readable &= !(1 << handle);
+    }
+
+    /// Forgets the readiness of a socket
+    /// when its handle is freed
+    pub(crate) fn clear(&mut self, handle: u8) {
+        self.readable &= !(1 << handle);
+        self.writable &= !(1 << handle);
+        self.closed &= !(1 << handle);
+    }
+
+    /// Gets the readiness of only the given sockets
+    pub(crate) fn select(&self, sockets: &[&TcpSocket]) -> Self {
+        let mask = sockets
+            .iter()
+            .fold(0u16, |mask, socket| mask | 1 << socket.handle);
+        Self {
+            readable: self.readable & mask,
+            writable: self.writable & mask,
+            closed: self.closed & mask,
+        }
+    }
+}
+
+/// Converts a port to network byte order
+/// for a socket frame. Register values are
+/// little endian, socket ports are not
+pub fn u16_to_be_bytes(value: u16) -> [u8; 2] {
+    value.to_be_bytes()
+}
+
+/// Converts an ipv4 address to network
+/// byte order for a socket frame
+pub fn ipv4_to_bytes(addr: Ipv4Addr) -> [u8; 4] {
+    addr.octets()
+}
+
+/// Converts an ipv4 address and port to the
+/// socket address in a socket frame. The family
+/// is little endian, the port and address are
+/// in network byte order
+pub fn sockaddr_to_bytes(addr: Ipv4Addr, port: u16) -> [u8; SOCKADDR_SIZE] {
+    let family = AF_INET.to_le_bytes();
+    let port = u16_to_be_bytes(port);
+    let ip = ipv4_to_bytes(addr);
+    [
+        family[0], family[1], port[0], port[1], ip[0], ip[1], ip[2], ip[3],
+    ]
+}
@@ -1,5 +1,6 @@
 use crate::crc::crc7;
-use crate::error::Error;
+use crate::error::{Error, SpiErrorContext};
+use crate::types::InitConfig;
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::OutputPin;
 
@@ -15,6 +16,9 @@ pub mod commands {
     pub const CMD_INTERNAL_WRITE: u8 = 0xc3; // type C
     pub const CMD_INTERNAL_READ: u8 = 0xc4; // type A
     pub const CMD_TERMINATE: u8 = 0xc5; // type A
+    /// Repeat is the same size as a single read and
+    /// is followed by a skipped byte, so polling a
+    /// register with it doesn't save any spi traffic
     pub const CMD_REPEAT: u8 = 0xc6; // type A
     pub const CMD_DMA_EXT_WRITE: u8 = 0xc7; // type C
     pub const CMD_DMA_EXT_READ: u8 = 0xc8; // type C
@@ -30,6 +34,11 @@ mod sizes {
     pub const RESPONSE: usize = 2;
     pub const DATA_START: usize = 1;
     pub const DATA: usize = 4;
+    // Byte skipped before the response
+    // to terminate, repeat and reset
+    pub const SKIP: usize = 1;
+    // Extra byte read in case the response is late
+    pub const PADDING: usize = 1;
     // Command size without crc bit
     pub const TYPE_A: usize = 4;
     pub const TYPE_B: usize = 6;
@@ -102,6 +111,10 @@ where
     cs: O,
     crc: bool,
     crc_disabled: bool,
+    read_retries: u32,
+    write_retries: u32,
+    max_transfer: usize,
+    last_error: Option<SpiErrorContext>,
 }
 
 impl<SPI, O> SpiBus<SPI, O>
@@ -116,9 +129,32 @@ where
             cs,
             crc,
             crc_disabled: false,
+            read_retries: InitConfig::DEFAULT_READ_RETRIES,
+            write_retries: InitConfig::DEFAULT_WRITE_RETRIES,
+            max_transfer: usize::MAX,
+            last_error: None,
         }
     }
 
+    /// Sets the number of times a data read
+    /// polls for the Atwinc1500 to be ready
+    pub fn set_read_retries(&mut self, retries: u32) {
+        self.read_retries = retries;
+    }
+
+    /// Sets the number of times a data write polls
+    /// for the Atwinc1500 to acknowledge the data
+    pub fn set_write_retries(&mut self, retries: u32) {
+        self.write_retries = retries;
+    }
+
+    /// Sets the largest number of bytes passed to
+    /// a single spi transfer. Larger transfers are
+    /// split while chip select stays low
+    pub fn set_max_transfer(&mut self, max_transfer: usize) {
+        self.max_transfer = max_transfer.max(1);
+    }
+
     /// Pulls the chip select high
     /// as it is active low
     pub fn init_cs(&mut self) -> Result<(), Error> {
@@ -134,13 +170,22 @@ where
         Ok(())
     }
 
+    /// Sets crc_disabled to false, as the
+    /// Atwinc1500 enables crc when it is reset
+    pub fn crc_enabled(&mut self) -> Result<(), Error> {
+        self.crc_disabled = false;
+        Ok(())
+    }
+
     /// Sends some data then receives some data on the spi bus
     fn transfer(&mut self, words: &'_ mut [u8]) -> Result<(), Error> {
         if self.cs.set_low().is_err() {
             return Err(Error::PinStateError);
         }
-        if self.spi.transfer(words).is_err() {
-            return Err(Error::SpiTransferError);
+        for chunk in words.chunks_mut(self.max_transfer) {
+            if self.spi.transfer(chunk).is_err() {
+                return Err(Error::SpiTransferError);
+            }
         }
         if self.cs.set_high().is_err() {
             return Err(Error::PinStateError);
@@ -162,10 +207,11 @@ where
         clockless: bool,
     ) -> Result<(), Error> {
         cmd_buffer[0] = command;
-        let mut crc_index: usize = 0;
+        // The crc follows the command bytes,
+        // so crc_index is the command size
+        let crc_index: usize;
         match command {
-            commands::CMD_DMA_WRITE => {}
-            commands::CMD_DMA_READ => {
+            commands::CMD_DMA_WRITE | commands::CMD_DMA_READ => {
                 cmd_buffer[1] = (address >> 16) as u8;
                 cmd_buffer[2] = (address >> 8) as u8;
                 cmd_buffer[3] = address as u8;
@@ -213,7 +259,7 @@ where
                 cmd_buffer[4] = (size >> 16) as u8;
                 cmd_buffer[5] = (size >> 8) as u8;
                 cmd_buffer[6] = size as u8;
-                crc_index = 0;
+                crc_index = sizes::TYPE_C;
             }
             commands::CMD_DMA_EXT_READ => {
                 cmd_buffer[1] = (address >> 16) as u8;
@@ -222,7 +268,7 @@ where
                 cmd_buffer[4] = (size >> 16) as u8;
                 cmd_buffer[5] = (size >> 8) as u8;
                 cmd_buffer[6] = size as u8;
-                crc_index = 0;
+                crc_index = sizes::TYPE_C;
             }
             commands::CMD_SINGLE_WRITE => {
                 cmd_buffer[1] = (address >> 16) as u8;
@@ -260,67 +306,74 @@ where
     /// Wraps the read_reg method to pass it the size
     /// of the command buffer based on crc being enabled
     pub fn read_register(&mut self, address: u32) -> Result<u32, Error> {
-        match self.crc_disabled {
+        let result = match self.crc_disabled {
             true => {
-                const SIZE: usize =
-                    sizes::TYPE_A + sizes::RESPONSE + sizes::DATA_START + sizes::DATA;
-                // 7..11 is the range of the data returned from the atwinc
-                // when crc is disabled and 4 is where the response from
-                // the atwinc starts
-                Ok(self.read_reg::<SIZE>(address, 7, 11, 4)?)
+                const SIZE: usize = sizes::TYPE_A
+                    + sizes::RESPONSE
+                    + sizes::DATA_START
+                    + sizes::DATA
+                    + sizes::PADDING;
+                // 4 is where the response from the atwinc
+                // starts when crc is disabled
+                self.read_reg::<SIZE>(address, 4)
             }
             false => {
-                const SIZE: usize =
-                    sizes::TYPE_A_CRC + sizes::RESPONSE + sizes::DATA_START + sizes::DATA;
-                // 8..12 is the range of the data returned from the atwinc
-                // when crc is enabled and 5 is where the response from
-                // the atwinc starts
-                Ok(self.read_reg::<SIZE>(address, 8, 12, 5)?)
+                const SIZE: usize = sizes::TYPE_A_CRC
+                    + sizes::RESPONSE
+                    + sizes::DATA_START
+                    + sizes::DATA
+                    + sizes::PADDING;
+                // 5 is where the response from the atwinc
+                // starts when crc is enabled
+                self.read_reg::<SIZE>(address, 5)
             }
-        }
+        };
+        self.record_error(read_command(address).0, address, result)
     }
 
     /// Reads a value from a register at a given address
     /// and returns it
+    ///
+    /// The response is accepted up to one byte before
+    /// or after response_start, as some firmware sends
+    /// an extra byte of zero padding before it
     fn read_reg<const S: usize>(
         &mut self,
         address: u32,
-        beg: usize,
-        end: usize,
         response_start: usize,
     ) -> Result<u32, Error> {
-        let cmd: u8;
-        let clockless: bool;
+        let (cmd, clockless) = read_command(address);
         let mut cmd_buffer: [u8; S] = [0; S];
-        // The Atmel driver does a clockless read
-        // if address is less than 0xff (0b11111111).
-        if address <= 0xff {
-            cmd = commands::CMD_INTERNAL_READ;
-            clockless = true;
-        } else {
-            cmd = commands::CMD_SINGLE_READ;
-            clockless = false;
-        }
         self.command(&mut cmd_buffer, cmd, address, 0, 0, clockless)?;
-        if cmd_buffer[response_start] != cmd || cmd_buffer[response_start + 2] & 0xf0 != 0xf0 {
-            return Err(Error::SpiReadRegisterError);
+        // response, status, data start then data (4 bytes)
+        let found = (response_start - 1..=response_start + 1)
+            .find(|&start| cmd_buffer[start] == cmd && cmd_buffer[start + 2] & 0xf0 == 0xf0);
+        match found {
+            Some(start) => Ok(combine_bytes_lsb!(cmd_buffer[start + 3..start + 7])),
+            None if is_bus_idle(&cmd_buffer) => Err(Error::SpiNoResponse),
+            None => Err(Error::SpiReadRegisterError),
         }
-        Ok(combine_bytes_lsb!(cmd_buffer[beg..end]))
     }
 
     /// Wraps the read method to change the command buffer size
     /// depending on crc being enabled or not
     pub fn read_data(&mut self, data: &mut [u8], address: u32, count: u32) -> Result<(), Error> {
-        match self.crc_disabled {
+        let result = match self.crc_disabled {
             true => {
                 const SIZE: usize = sizes::TYPE_C;
-                Ok(self.read::<SIZE>(data, address, count)?)
+                self.read::<SIZE>(data, address, count)
             }
             false => {
                 const SIZE: usize = sizes::TYPE_C_CRC;
-                Ok(self.read::<SIZE>(data, address, count)?)
+                self.read::<SIZE>(data, address, count)
             }
+        };
+        if result.is_err() {
+            // The original error is more useful than
+            // one from a failed terminate
+            self.terminate_transaction().ok();
         }
+        self.record_error(commands::CMD_DMA_EXT_READ, address, result)
     }
 
     /// Reads a block of data
@@ -335,30 +388,35 @@ where
         let mut response: [u8; sizes::RESPONSE + sizes::DATA_START] =
             [0; sizes::RESPONSE + sizes::DATA_START];
         self.command(&mut cmd_buffer, cmd, address, 0, count, false)?;
-        retry_while!(response[0] == 0, retries = 10, {
+        retry_while!(response[0] == 0, retries = self.read_retries, {
             self.transfer(&mut response)?;
         });
-        if response[0] == cmd {
-            self.transfer(data)?;
+        if response[0] == 0 {
+            return Err(Error::SpiReadDataTimeout);
+        }
+        if response[0] != cmd {
+            return Err(Error::SpiReadDataError);
         }
+        self.transfer(data)?;
         Ok(())
     }
 
     /// Wraps the read_reg method to pass it the size
     /// of the command buffer based on crc being enabled
     pub fn write_register(&mut self, address: u32, data: u32) -> Result<(), Error> {
-        match self.crc_disabled {
+        let result = match self.crc_disabled {
             // response starts at index 8
             true => {
                 const SIZE: usize = sizes::TYPE_D + sizes::RESPONSE;
-                Ok(self.write_reg::<SIZE>(address, data, 8)?)
+                self.write_reg::<SIZE>(address, data, 8)
             }
             // response starts at index 9
             false => {
                 const SIZE: usize = sizes::TYPE_D_CRC + sizes::RESPONSE;
-                Ok(self.write_reg::<SIZE>(address, data, 9)?)
+                self.write_reg::<SIZE>(address, data, 9)
             }
-        }
+        };
+        self.record_error(write_command(address).0, address, result)
     }
 
     /// Writes a value to a register at a given address
@@ -368,19 +426,12 @@ where
         data: u32,
         response_start: usize,
     ) -> Result<(), Error> {
-        let cmd: u8;
-        let clockless: bool;
+        let (cmd, clockless) = write_command(address);
         let mut cmd_buffer: [u8; S] = [0; S];
-        // The Atmel driver does a clockless write
-        // if address is less than 0x30 (0b00110000).
-        if address <= 0x30 {
-            cmd = commands::CMD_INTERNAL_WRITE;
-            clockless = true;
-        } else {
-            cmd = commands::CMD_SINGLE_WRITE;
-            clockless = false;
-        }
         self.command(&mut cmd_buffer, cmd, address, data, 0, clockless)?;
+        if is_bus_idle(&cmd_buffer) {
+            return Err(Error::SpiNoResponse);
+        }
         if cmd_buffer[response_start] != cmd || cmd_buffer[response_start + 1] != 0 {
             return Err(Error::SpiWriteRegisterError);
         }
@@ -390,16 +441,85 @@ where
     /// Wraps the write method to change the command buffer size
     /// depending on crc being enabled or not
     pub fn write_data(&mut self, data: &mut [u8], address: u32, count: u32) -> Result<(), Error> {
-        match self.crc_disabled {
+        let result = match self.crc_disabled {
             true => {
                 const SIZE: usize = sizes::TYPE_C;
-                Ok(self.write::<SIZE>(data, address, count)?)
+                self.write::<SIZE>(data, address, count)
             }
             false => {
                 const SIZE: usize = sizes::TYPE_C_CRC;
-                Ok(self.write::<SIZE>(data, address, count)?)
+                self.write::<SIZE>(data, address, count)
             }
+        };
+        if result.is_err() {
+            self.terminate_transaction().ok();
         }
+        self.record_error(commands::CMD_DMA_EXT_WRITE, address, result)
+    }
+
+    /// Aborts a data read or write the Atwinc1500 is
+    /// still in the middle of, so a failed transfer
+    /// doesn't leave its dma engine waiting on the
+    /// host. Called by read_data and write_data when
+    /// they fail
+    pub fn terminate_transaction(&mut self) -> Result<(), Error> {
+        match self.crc_disabled {
+            // response starts at index 5
+            true => {
+                const SIZE: usize = sizes::TYPE_A + sizes::SKIP + sizes::RESPONSE + sizes::PADDING;
+                self.terminate::<SIZE>(sizes::TYPE_A + sizes::SKIP)
+            }
+            // response starts at index 6
+            false => {
+                const SIZE: usize =
+                    sizes::TYPE_A_CRC + sizes::SKIP + sizes::RESPONSE + sizes::PADDING;
+                self.terminate::<SIZE>(sizes::TYPE_A_CRC + sizes::SKIP)
+            }
+        }
+    }
+
+    /// Sends CMD_TERMINATE and checks the response,
+    /// allowing it to arrive a byte early or late
+    fn terminate<const S: usize>(&mut self, response_start: usize) -> Result<(), Error> {
+        let cmd: u8 = commands::CMD_TERMINATE;
+        let mut cmd_buffer: [u8; S] = [0; S];
+        self.command(&mut cmd_buffer, cmd, 0, 0, 0, false)?;
+        match (response_start - 1..=response_start + 1)
+            .find(|&start| cmd_buffer[start] == cmd && cmd_buffer[start + 1] == 0)
+        {
+            Some(_) => Ok(()),
+            None => Err(Error::SpiTerminateError),
+        }
+    }
+
+    /// Gets the command and address of the
+    /// last spi transaction that failed
+    pub fn last_error(&self) -> Option<SpiErrorContext> {
+        self.last_error
+    }
+
+    /// Gets the spi peripheral, for changing its
+    /// clock between transactions
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    /// Keeps the command and address
+    /// of a failed spi transaction
+    fn record_error<T>(
+        &mut self,
+        command: u8,
+        address: u32,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if let Err(error) = result {
+            self.last_error = Some(SpiErrorContext {
+                command,
+                address,
+                error,
+            });
+        }
+        result
     }
 
     /// Writes a block of data to the atwinc1500
@@ -415,14 +535,57 @@ where
         let data_mark: u8 = SpiPacket::Last as u8;
         self.command(&mut cmd_buffer, cmd, address, 0, count, false)?;
         self.transfer(&mut response)?;
-        if response[0] == cmd {
-            self.transfer(&mut [data_mark])?;
-            self.transfer(data)?;
-            response[0] = 0;
-            retry_while!(response[0] != 0xc3, retries = 10, {
+        if response[0] != cmd {
+            return Err(Error::SpiWriteDataError);
+        }
+        self.transfer(&mut [data_mark])?;
+        self.transfer(data)?;
+        // 0x00 and 0xff mean the Atwinc1500 is still
+        // busy, any other value than 0xc3 is a nak
+        response[0] = 0;
+        retry_while!(
+            response[0] == 0 || response[0] == 0xff,
+            retries = self.write_retries,
+            {
+                response[0] = 0;
                 self.transfer(&mut response[0..1])?;
-            });
+            }
+        );
+        if response[0] != 0xc3 {
+            return Err(Error::SpiWriteDataError);
         }
         Ok(())
     }
 }
+
+/// Gets the command used to read a register and
+/// whether it is clockless. The Atmel driver does
+/// a clockless read if address is less than 0xff
+/// (0b11111111)
+fn read_command(address: u32) -> (u8, bool) {
+    if address <= 0xff {
+        (commands::CMD_INTERNAL_READ, true)
+    } else {
+        (commands::CMD_SINGLE_READ, false)
+    }
+}
+
+/// Gets the command used to write a register and
+/// whether it is clockless. The Atmel driver does
+/// a clockless write if address is less than 0x30
+/// (0b00110000)
+fn write_command(address: u32) -> (u8, bool) {
+    if address <= 0x30 {
+        (commands::CMD_INTERNAL_WRITE, true)
+    } else {
+        (commands::CMD_SINGLE_WRITE, false)
+    }
+}
+
+/// Returns true if every byte clocked back was
+/// 0x00 or every byte was 0xff. That is what a
+/// floating or shorted MISO line reads as, so
+/// the Atwinc1500 never answered at all
+fn is_bus_idle(received: &[u8]) -> bool {
+    received.iter().all(|b| *b == 0x00) || received.iter().all(|b| *b == 0xff)
+}
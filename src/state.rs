@@ -0,0 +1,175 @@
+//! Driver state updated by the host interface callbacks
+use crate::dns::{DnsCache, HOSTNAME_MAX_SIZE};
+use crate::event::{Event, EventQueue};
+use crate::socket::{PingResult, SocketReadiness, SocketState, TCP_SOCK_MAX};
+use crate::types::{FirmwareVersion, IpConfig, MacAddress, RANDOM_BUFFER_SIZE};
+use crate::wifi::{
+    ConnectionInfo, ScanResult, StateChangeErrorCode, Status, TxPower, MAX_MULTICAST_FILTERS,
+    MAX_SCAN_RESULTS,
+};
+use core::net::Ipv4Addr;
+
+/// Holds everything the Atwinc1500 has reported
+/// back to the host through handle_events
+pub(crate) struct State {
+    pub firmware_version: Option<FirmwareVersion>,
+    pub status: Status,
+    pub disconnect_pending: bool,
+    pub scan_in_progress: bool,
+    pub dns_in_progress: bool,
+    pub ping_in_progress: bool,
+    pub ping_result: Option<PingResult>,
+    pub dns_cache: DnsCache,
+    pub dns_host: [u8; HOSTNAME_MAX_SIZE],
+    pub dns_host_len: usize,
+    pub dns_result: Option<Ipv4Addr>,
+    pub num_ap: u8,
+    pub scan_result: Option<ScanResult>,
+    pub scan_results: [Option<ScanResult>; MAX_SCAN_RESULTS],
+    pub ip_config: Option<IpConfig>,
+    pub rssi: Option<i8>,
+    pub tx_power: Option<TxPower>,
+    pub multicast_filters: [MacAddress; MAX_MULTICAST_FILTERS],
+    pub multicast_count: usize,
+    pub random_bytes: [u8; RANDOM_BUFFER_SIZE],
+    pub random_len: Option<usize>,
+    pub conn_info: Option<ConnectionInfo>,
+    pub events: EventQueue,
+    pub now_ms: u64,
+    pub connected_at_ms: Option<u64>,
+    pub reconnect_count: u32,
+    pub last_disconnect_ms: Option<u64>,
+    pub last_disconnect_reason: Option<StateChangeErrorCode>,
+    pub sockets: [Option<SocketState>; TCP_SOCK_MAX],
+    pub next_session: u16,
+    pub socket_readiness: SocketReadiness,
+}
+
+impl State {
+    /// Creates a new State in the disconnected status
+    pub fn new() -> Self {
+        Self {
+            firmware_version: None,
+            status: Status::Disconnected,
+            disconnect_pending: false,
+            scan_in_progress: false,
+            dns_in_progress: false,
+            ping_in_progress: false,
+            ping_result: None,
+            dns_cache: DnsCache::new(),
+            dns_host: [0; HOSTNAME_MAX_SIZE],
+            dns_host_len: 0,
+            dns_result: None,
+            num_ap: 0,
+            scan_result: None,
+            scan_results: [None; MAX_SCAN_RESULTS],
+            ip_config: None,
+            rssi: None,
+            tx_power: None,
+            multicast_filters: [MacAddress([0; 6]); MAX_MULTICAST_FILTERS],
+            multicast_count: 0,
+            random_bytes: [0; RANDOM_BUFFER_SIZE],
+            random_len: None,
+            conn_info: None,
+            events: EventQueue::new(),
+            now_ms: 0,
+            connected_at_ms: None,
+            reconnect_count: 0,
+            last_disconnect_ms: None,
+            last_disconnect_reason: None,
+            sockets: [None; TCP_SOCK_MAX],
+            next_session: 0,
+            socket_readiness: SocketReadiness::default(),
+        }
+    }
+
+    /// Returns true if handle is open with session.
+    /// Replies for a closed socket whose handle was
+    /// reused carry the session of the old socket
+    pub fn is_current_session(&self, handle: u8, session: u16) -> bool {
+        matches!(self.sockets.get(handle as usize), Some(Some(s)) if s.session == session)
+    }
+
+    /// Queues a socket event and updates
+    /// the readiness of its socket
+    pub fn push_socket_event(&mut self, event: Event) {
+        if let Event::SocketClosed { handle } = event {
+            if let Some(Some(socket)) = self.sockets.get_mut(handle as usize) {
+                socket.closed = true;
+            }
+        }
+        self.socket_readiness.update(event);
+        self.events.push(event);
+    }
+
+    /// Copies the fields read by white box tests
+    #[cfg(feature = "test-internals")]
+    pub fn debug_state(&self) -> DebugState {
+        let mut sockets = [None; TCP_SOCK_MAX];
+        for (debug, socket) in sockets.iter_mut().zip(self.sockets.iter()) {
+            *debug = socket.map(|s| DebugSocket {
+                session: s.session,
+                buffered: s.rx.len(),
+                recv_pending: s.recv_pending,
+                connecting: s.connecting,
+                connected: s.connected,
+                closed: s.closed,
+            });
+        }
+        DebugState {
+            status: self.status,
+            scan_in_progress: self.scan_in_progress,
+            dns_in_progress: self.dns_in_progress,
+            ping_in_progress: self.ping_in_progress,
+            num_ap: self.num_ap,
+            connected_at_ms: self.connected_at_ms,
+            queued_events: self.events.len(),
+            next_session: self.next_session,
+            sockets,
+        }
+    }
+}
+
+/// Snapshot of the driver's internal state,
+/// for asserting on the connect, scan and
+/// socket state machines in tests
+#[cfg(feature = "test-internals")]
+#[derive(Copy, Clone, Debug)]
+pub struct DebugState {
+    /// Connection status
+    pub status: Status,
+    /// A scan was requested and isn't done
+    pub scan_in_progress: bool,
+    /// A dns resolve was requested and isn't done
+    pub dns_in_progress: bool,
+    /// A ping was requested and isn't done
+    pub ping_in_progress: bool,
+    /// Networks found in the last scan
+    pub num_ap: u8,
+    /// Clock time of the last connection
+    pub connected_at_ms: Option<u64>,
+    /// Events waiting to be read with next_event
+    pub queued_events: usize,
+    /// Session given to the last opened socket
+    pub next_session: u16,
+    /// Open tcp sockets by handle
+    pub sockets: [Option<DebugSocket>; TCP_SOCK_MAX],
+}
+
+/// Snapshot of an open tcp socket
+#[cfg(feature = "test-internals")]
+#[derive(Copy, Clone, Debug)]
+pub struct DebugSocket {
+    /// Session sent with its requests
+    pub session: u16,
+    /// Received bytes not read yet
+    pub buffered: usize,
+    /// A receive request is waiting on data
+    pub recv_pending: bool,
+    /// A connect request is waiting on its response
+    pub connecting: bool,
+    /// The socket connected to its peer
+    pub connected: bool,
+    /// The peer closed the socket or it failed
+    pub closed: bool,
+}
@@ -1,13 +1,267 @@
 //! Public type implementations
+use crate::error::Error;
 use core::fmt;
+use core::net::Ipv4Addr;
 #[cfg(target_os = "none")]
 use defmt::{write as defmt_write, Format, Formatter};
 
 /// Firmware version of 3 bytes in the format x.x.x
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Debug)]
 pub struct FirmwareVersion(pub [u8; 3]);
 /// Mac address of 6 bytes in the format x:x:x:x:x:x
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct MacAddress(pub [u8; 6]);
 
+/// Features supported by the firmware on the Atwinc1500
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Features {
+    /// Firmware version the features are based on
+    pub firmware_version: FirmwareVersion,
+    /// Tls client sockets, firmware 19.3.0 and newer
+    pub tls: bool,
+    /// The newer connection format, firmware 19.6.1 and newer
+    pub new_connection_format: bool,
+    /// Erasing saved networks, firmware 19.6.1 and newer
+    pub erase_saved_credentials: bool,
+    /// Tcp keepalive socket options, firmware 19.6.1 and newer
+    pub tcp_keepalive: bool,
+}
+
+impl From<FirmwareVersion> for Features {
+    fn from(version: FirmwareVersion) -> Self {
+        Self {
+            firmware_version: version,
+            tls: version >= FirmwareVersion([19, 3, 0]),
+            new_connection_format: version >= FirmwareVersion([19, 6, 1]),
+            erase_saved_credentials: version >= FirmwareVersion([19, 6, 1]),
+            tcp_keepalive: version >= FirmwareVersion([19, 6, 1]),
+        }
+    }
+}
+
+/// Values written to the Atwinc1500 during initialization
+///
+/// The defaults match the Atmel host driver this
+/// crate is based on and work with 19.x firmware
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct InitConfig {
+    /// Written to NMI_STATE_REG before the firmware starts.
+    /// The high 16 bits are the firmware version the driver
+    /// was built against and the low 16 bits are the driver
+    /// version. Each half is major << 8 | minor << 4 | patch
+    pub version_info: u32,
+    /// Written to rNMI_GP_REG_1 before the firmware starts.
+    /// Bit 1 enables the power management unit and bit 8
+    /// is reserved and set by the Atmel driver
+    ///
+    /// Bit 3 asks the firmware for ethernet (bypass) mode,
+    /// which needs the bypass build of the firmware. The
+    /// firmware doesn't report which build it is, so the
+    /// driver can't tell socket and bypass firmware apart
+    /// and leaves bit 3 clear for the socket firmware
+    pub conf: u32,
+    /// Milliseconds the reset pin is held low
+    pub reset_low_ms: u32,
+    /// Milliseconds to wait after reset is released.
+    /// The Atmel driver uses 1ms low and 10ms high
+    pub reset_high_ms: u32,
+    /// Number of times the efuse is polled during
+    /// initialization, 1 second apart
+    pub efuse_retries: u32,
+    /// Number of times the boot rom is polled during
+    /// initialization, 1 second apart
+    pub bootrom_retries: u32,
+    /// Number of times initialization polls for the
+    /// firmware to start, 1 second apart, before
+    /// failing with Error::InitTimeout
+    pub firmware_start_retries: u32,
+    /// Number of times a data read polls for the
+    /// Atwinc1500 to be ready before failing with
+    /// Error::SpiReadDataTimeout
+    pub read_retries: u32,
+    /// Number of times a data write polls for the
+    /// Atwinc1500 to acknowledge the data before
+    /// failing with Error::SpiWriteDataError
+    pub write_retries: u32,
+    /// Number of times sending a packet polls for the
+    /// Atwinc1500 to allocate a buffer before failing
+    /// with HifError::AllocationFailed
+    pub allocation_retries: u32,
+    /// Largest number of bytes passed to a single
+    /// spi transfer, for spi drivers that limit the
+    /// size of a transfer
+    pub max_transfer: usize,
+}
+
+impl InitConfig {
+    /// Firmware 19.5.2 and driver 19.3.0
+    pub const DEFAULT_VERSION_INFO: u32 = 0x13521330;
+    /// Power management unit enabled
+    pub const DEFAULT_CONF: u32 = 0x102;
+    /// Default efuse_retries
+    pub const DEFAULT_EFUSE_RETRIES: u32 = 10;
+    /// Default bootrom_retries
+    pub const DEFAULT_BOOTROM_RETRIES: u32 = 3;
+    /// Default firmware_start_retries
+    pub const DEFAULT_FIRMWARE_START_RETRIES: u32 = 20;
+    /// Default read_retries
+    pub const DEFAULT_READ_RETRIES: u32 = 10;
+    /// Default write_retries
+    pub const DEFAULT_WRITE_RETRIES: u32 = 10;
+    /// Default allocation_retries
+    pub const DEFAULT_ALLOCATION_RETRIES: u32 = 100;
+
+    /// Packs a firmware and driver version
+    /// into the format used by version_info
+    pub fn make_version_info(firmware: FirmwareVersion, driver: FirmwareVersion) -> u32 {
+        let pack = |v: FirmwareVersion| {
+            (v.0[0] as u32) << 8 | ((v.0[1] & 0x0f) as u32) << 4 | (v.0[2] & 0x0f) as u32
+        };
+        pack(firmware) << 16 | pack(driver)
+    }
+
+    /// Gets the driver version from version_info
+    pub fn driver_version(&self) -> FirmwareVersion {
+        FirmwareVersion([
+            ((self.version_info >> 8) & 0xff) as u8,
+            ((self.version_info >> 4) & 0x0f) as u8,
+            (self.version_info & 0x0f) as u8,
+        ])
+    }
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            version_info: Self::DEFAULT_VERSION_INFO,
+            conf: Self::DEFAULT_CONF,
+            reset_low_ms: 1000,
+            reset_high_ms: 1000,
+            efuse_retries: Self::DEFAULT_EFUSE_RETRIES,
+            bootrom_retries: Self::DEFAULT_BOOTROM_RETRIES,
+            firmware_start_retries: Self::DEFAULT_FIRMWARE_START_RETRIES,
+            read_retries: Self::DEFAULT_READ_RETRIES,
+            write_retries: Self::DEFAULT_WRITE_RETRIES,
+            allocation_retries: Self::DEFAULT_ALLOCATION_RETRIES,
+            max_transfer: usize::MAX,
+        }
+    }
+}
+
+/// Largest number of random bytes requested at once.
+/// The firmware allows up to 1588, this keeps the
+/// buffer held by the driver small
+pub const RANDOM_BUFFER_SIZE: usize = 32;
+
+/// Size of an ip configuration sent by the Atwinc1500
+pub(crate) const IP_CONFIG_SIZE: usize = 20;
+
+/// Ip configuration assigned by dhcp
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IpConfig {
+    /// Ip address of the Atwinc1500
+    pub ip: Ipv4Addr,
+    /// Gateway address
+    pub gateway: Ipv4Addr,
+    /// Dns server address
+    pub dns: Ipv4Addr,
+    /// Subnet mask
+    pub subnet_mask: Ipv4Addr,
+    /// Dhcp lease time in seconds
+    pub lease_time: u32,
+}
+
+impl From<[u8; IP_CONFIG_SIZE]> for IpConfig {
+    /// Converts the dhcp configuration response
+    /// into an IpConfig. Addresses are in network
+    /// order and the lease time is little endian
+    fn from(data: [u8; IP_CONFIG_SIZE]) -> Self {
+        IpConfig {
+            ip: Ipv4Addr::new(data[0], data[1], data[2], data[3]),
+            gateway: Ipv4Addr::new(data[4], data[5], data[6], data[7]),
+            dns: Ipv4Addr::new(data[8], data[9], data[10], data[11]),
+            subnet_mask: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+            lease_time: combine_bytes_lsb!(data[16..20]),
+        }
+    }
+}
+
+/// Flash id and size of the spi flash
+/// inside the Atwinc1500
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FlashInfo {
+    /// Jedec id read from the flash. The low byte is
+    /// the manufacturer and the third byte the capacity
+    pub id: u32,
+    /// Size of the flash in megabits
+    pub size_mbit: u32,
+}
+
+impl FlashInfo {
+    /// Gets the size of the flash in bytes
+    pub fn size_bytes(&self) -> u32 {
+        self.size_mbit * 1024 * 1024 / 8
+    }
+
+    /// Returns true for the 8 megabit variant of
+    /// the Atwinc1500 (Atwinc1510) and false for the
+    /// 4 megabit Atwinc1500
+    pub fn is_8mbit(&self) -> bool {
+        self.size_mbit >= 8
+    }
+}
+
+impl TryFrom<u32> for FlashInfo {
+    type Error = Error;
+
+    /// Converts the jedec id into FlashInfo. The
+    /// capacity byte is a power of two where 0x11
+    /// is 1 megabit
+    fn try_from(id: u32) -> Result<Self, Self::Error> {
+        let capacity = (id >> 16) & 0xff;
+        if id == 0xffffffff || !(0x11..0x11 + 16).contains(&capacity) {
+            return Err(Error::FlashNotDetected);
+        }
+        Ok(FlashInfo {
+            id,
+            size_mbit: 1 << (capacity - 0x11),
+        })
+    }
+}
+
+/// The chip select pin. The pins passed to new
+/// are wrapped in their role so swapping the chip
+/// select and reset pins is a compile error
+#[derive(Debug)]
+pub struct ChipSelect<O>(pub O);
+
+/// The reset pin
+#[derive(Debug)]
+pub struct Reset<O>(pub O);
+
+/// The wake pin
+#[derive(Debug)]
+pub struct Wake<O>(pub O);
+
+/// Result of sending a packet to the Atwinc1500
+///
+/// The Atwinc1500 allocates a new buffer for every
+/// packet and does not report how much space it has
+/// left, so each send has to poll for its allocation.
+/// allocation_polls shows how long that took and can
+/// be used to pace sends
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SendInfo {
+    /// Address of the buffer the Atwinc1500
+    /// allocated for the packet
+    pub address: u32,
+    /// Number of bytes written, including the header
+    pub length: u16,
+    /// Number of extra polls before the
+    /// Atwinc1500 allocated the buffer
+    pub allocation_polls: u32,
+}
+
 #[cfg(target_os = "none")]
 impl Format for FirmwareVersion {
     fn format(&self, fmt: Formatter) {
@@ -45,3 +299,44 @@ impl fmt::Display for MacAddress {
         )
     }
 }
+
+#[cfg(target_os = "none")]
+impl Format for IpConfig {
+    fn format(&self, fmt: Formatter) {
+        let ip = self.ip.octets();
+        let gateway = self.gateway.octets();
+        let dns = self.dns.octets();
+        let mask = self.subnet_mask.octets();
+        defmt_write!(
+            fmt,
+            "ip: {}.{}.{}.{}, gateway: {}.{}.{}.{}, dns: {}.{}.{}.{}, mask: {}.{}.{}.{}, lease: {}s",
+            ip[0],
+            ip[1],
+            ip[2],
+            ip[3],
+            gateway[0],
+            gateway[1],
+            gateway[2],
+            gateway[3],
+            dns[0],
+            dns[1],
+            dns[2],
+            dns[3],
+            mask[0],
+            mask[1],
+            mask[2],
+            mask[3],
+            self.lease_time
+        )
+    }
+}
+
+impl fmt::Display for IpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ip: {}, gateway: {}, dns: {}, mask: {}, lease: {}s",
+            self.ip, self.gateway, self.dns, self.subnet_mask, self.lease_time
+        )
+    }
+}
@@ -1,4 +1,10 @@
 //! Wifi connection items
+use crate::error::{Error, HifError};
+use crate::types::MacAddress;
+use core::fmt;
+use core::net::Ipv4Addr;
+#[cfg(target_os = "none")]
+use defmt::{write as defmt_write, Format, Formatter};
 
 // constants
 const MAX_SSID_LEN: usize = 33;
@@ -10,13 +16,38 @@ const _WEP_40_KEY_STRING_SIZE: usize = 10;
 const _WEP_104_KEY_STRING_SIZE: usize = 26;
 const _WEP_KEY_MAX_INDEX: usize = 4;
 
+/// Size of a scan result sent by the Atwinc1500
+pub(crate) const SCAN_RESULT_SIZE: usize = 44;
+/// Size of a connection info sent by the Atwinc1500
+pub(crate) const CONN_INFO_SIZE: usize = 48;
+/// Number of scan results kept by request_all_scan_results
+pub const MAX_SCAN_RESULTS: usize = 16;
+/// Number of multicast mac addresses the
+/// driver keeps track of
+pub const MAX_MULTICAST_FILTERS: usize = 8;
+
 /// Connection format for older firmware
-pub type OldConnection = [u8; 106];
-/// Connection format for newer firmware
-pub type NewConnection = ([u8; 48], [u8; 108]);
+pub type OldConnection = [u8; 108];
+/// Connection format for newer firmware, the
+/// header followed by the passphrase of a WPA
+/// PSK network
+pub type NewConnection = ([u8; 48], [u8; 100]);
+/// Size of the credentials header in the
+/// newer connection format
+const CONN_CRED_HEADER_SIZE: usize = 12;
+/// Saves the credentials to flash
+const CRED_STORE_FLAG: u8 = 0x01;
+/// Restricts the connection to au8Bssid
+const CONN_BSSID_FLAG: u8 = 0x01;
 
 /// This represents the type
 /// of security a network uses
+///
+/// WPA3-SAE is not supported by any Atwinc1500
+/// firmware, a network in WPA2/WPA3 transition
+/// mode can be joined with WpaPsk. WPA3 only
+/// networks can not be joined
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum SecurityType {
     /// Wi-Fi network is not secured
     Open = 1,
@@ -28,10 +59,24 @@ pub enum SecurityType {
     Sec8021x = 4,
 }
 
+impl TryFrom<u8> for SecurityType {
+    type Error = Error;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        match val {
+            1 => Ok(SecurityType::Open),
+            2 => Ok(SecurityType::WpaPsk),
+            3 => Ok(SecurityType::Wep),
+            4 => Ok(SecurityType::Sec8021x),
+            _ => Err(Error::InvalidSecurityType),
+        }
+    }
+}
+
 /// Wireless channels
 ///
 /// The default channel is any
-#[derive(Default)]
+#[derive(Copy, Clone, Default, Debug)]
 pub enum Channel {
     /// Channel 1
     Ch1 = 1,
@@ -70,15 +115,526 @@ pub enum Channel {
     Any = 255,
 }
 
+/// Regions that limit which channels the
+/// Atwinc1500 will use. Each value is a
+/// mask of the allowed channels
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ScanRegion {
+    /// Channels 1 through 11
+    NorthAmerica = 0x7ff,
+    /// Channels 1 through 13
+    Europe = 0x1fff,
+    /// Channels 1 through 14
+    Asia = 0x3fff,
+}
+
+/// Transmit power levels of the Atwinc1500
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TxPower {
+    /// Highest transmit power (default)
+    High = 1,
+    /// Medium transmit power
+    Medium = 2,
+    /// Lowest transmit power
+    Low = 3,
+}
+
+impl ScanRegion {
+    /// Gets the region for an ISO 3166 country code.
+    /// The firmware only supports these three regions,
+    /// so countries that allow channels 1 through 11 map
+    /// to NorthAmerica, Japan maps to Asia, and every other
+    /// country maps to Europe (channels 1 through 13)
+    pub fn from_country(mut code: [u8; 2]) -> Result<Self, Error> {
+        if !code.iter().all(|c| c.is_ascii_alphabetic()) {
+            return Err(Error::InvalidCountryCode);
+        }
+        code.make_ascii_uppercase();
+        match &code {
+            b"US" | b"CA" | b"MX" | b"TW" | b"PR" => Ok(ScanRegion::NorthAmerica),
+            b"JP" => Ok(ScanRegion::Asia),
+            _ => Ok(ScanRegion::Europe),
+        }
+    }
+}
+
+/// Options for how long the Atwinc1500
+/// listens on each channel during a scan
+///
+/// The options are kept by the Atwinc1500
+/// until they are set again or it is reset
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ScanOptions {
+    /// Number of slots spent on each channel,
+    /// at least 1
+    pub num_slots: u8,
+    /// Length of a slot in milliseconds,
+    /// from 10 to 250
+    pub slot_time_ms: u8,
+    /// Number of probe requests sent
+    /// each slot, 1 or 2
+    pub probes_per_slot: u8,
+    /// Networks with a weaker rssi than this are
+    /// not reported, from -99 to -1 dBm
+    pub rssi_threshold: i8,
+}
+
+impl ScanOptions {
+    /// Shortest scan the firmware allows, a
+    /// single 10ms slot with one probe request
+    pub const QUICK: ScanOptions = ScanOptions {
+        num_slots: 1,
+        slot_time_ms: 10,
+        probes_per_slot: 1,
+        rssi_threshold: -99,
+    };
+
+    /// Converts the options into the scan option
+    /// request, returning Error::InvalidScanOptions
+    /// if a value is out of range
+    pub(crate) fn to_bytes(self) -> Result<[u8; 4], Error> {
+        if self.num_slots == 0
+            || !(10..=250).contains(&self.slot_time_ms)
+            || !(1..=2).contains(&self.probes_per_slot)
+            || !(-99..=-1).contains(&self.rssi_threshold)
+        {
+            return Err(Error::InvalidScanOptions);
+        }
+        Ok([
+            self.num_slots,
+            self.slot_time_ms,
+            self.probes_per_slot,
+            self.rssi_threshold as u8,
+        ])
+    }
+}
+
+impl Default for ScanOptions {
+    /// The defaults of the Atwinc1500 firmware
+    fn default() -> Self {
+        Self {
+            num_slots: 2,
+            slot_time_ms: 30,
+            probes_per_slot: 2,
+            rssi_threshold: -99,
+        }
+    }
+}
+
+impl TryFrom<u8> for Channel {
+    type Error = Error;
+
+    /// Converts a channel number reported by
+    /// the Atwinc1500 into a Channel
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        match val {
+            1 => Ok(Channel::Ch1),
+            2 => Ok(Channel::Ch2),
+            3 => Ok(Channel::Ch3),
+            4 => Ok(Channel::Ch4),
+            5 => Ok(Channel::Ch5),
+            6 => Ok(Channel::Ch6),
+            7 => Ok(Channel::Ch7),
+            8 => Ok(Channel::Ch8),
+            9 => Ok(Channel::Ch9),
+            10 => Ok(Channel::Ch10),
+            11 => Ok(Channel::Ch11),
+            12 => Ok(Channel::Ch12),
+            13 => Ok(Channel::Ch13),
+            14 => Ok(Channel::Ch14),
+            15 => Ok(Channel::Ch15),
+            16 => Ok(Channel::Ch16),
+            255 => Ok(Channel::Any),
+            _ => Err(Error::InvalidChannel),
+        }
+    }
+}
+
+/// A network found during a scan
+#[derive(Copy, Clone, Debug)]
+pub struct ScanResult {
+    /// Index of this result in the scan list
+    pub index: u8,
+    /// Received signal strength in dBm
+    pub rssi: i8,
+    /// Security type of the network
+    pub auth_type: u8,
+    /// Channel the network is on
+    pub channel: u8,
+    /// Mac address of the access point
+    pub bssid: MacAddress,
+    /// Null terminated ssid of the network
+    pub ssid: [u8; MAX_SSID_LEN],
+}
+
+impl ScanResult {
+    /// Gets the channel of the network as a Channel.
+    /// Channels the driver doesn't know about
+    /// become Channel::Any
+    pub fn channel_enum(&self) -> Channel {
+        Channel::try_from(self.channel).unwrap_or_default()
+    }
+
+    /// Gets the ssid without the null terminator
+    pub fn ssid_bytes(&self) -> &[u8] {
+        let len = self
+            .ssid
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_SSID_LEN);
+        &self.ssid[..len]
+    }
+
+    /// Gets the ssid as a str. Ssids are not
+    /// required to be utf-8, so this is None
+    /// when the ssid is not valid utf-8
+    pub fn ssid_str(&self) -> Option<&str> {
+        core::str::from_utf8(self.ssid_bytes()).ok()
+    }
+
+    /// Gets the signal quality as a percentage
+    /// from 0 to 100. See rssi_to_quality
+    pub fn quality(&self) -> u8 {
+        rssi_to_quality(self.rssi)
+    }
+
+    /// Gets the security type of the network, or
+    /// None if the driver doesn't know auth_type
+    pub fn security_type(&self) -> Option<SecurityType> {
+        SecurityType::try_from(self.auth_type).ok()
+    }
+
+    /// Returns true if the network uses WPA/WPA2
+    /// Enterprise (802.1x) authentication
+    pub fn is_enterprise(&self) -> bool {
+        self.security_type() == Some(SecurityType::Sec8021x)
+    }
+
+    /// Returns true if joining the network needs
+    /// a passphrase, key or enterprise credentials
+    pub fn requires_password(&self) -> bool {
+        matches!(
+            self.security_type(),
+            Some(SecurityType::WpaPsk | SecurityType::Wep | SecurityType::Sec8021x)
+        )
+    }
+}
+
+#[cfg(target_os = "none")]
+impl Format for ScanResult {
+    /// Ssids that aren't valid utf-8
+    /// are written as hex
+    fn format(&self, fmt: Formatter) {
+        match self.ssid_str() {
+            Some(ssid) => defmt_write!(fmt, "{=str}", ssid),
+            None => defmt_write!(fmt, "{=[u8]:02x}", self.ssid_bytes()),
+        }
+        defmt_write!(
+            fmt,
+            " ({}) rssi: {}, channel: {}",
+            self.bssid,
+            self.rssi,
+            self.channel
+        );
+    }
+}
+
+impl fmt::Display for ScanResult {
+    /// Ssids that aren't valid utf-8
+    /// are written as hex
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ssid_str() {
+            Some(ssid) => write!(f, "{}", ssid)?,
+            None => {
+                for byte in self.ssid_bytes() {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+        }
+        write!(
+            f,
+            " ({}) rssi: {}, channel: {}",
+            self.bssid, self.rssi, self.channel
+        )
+    }
+}
+
+impl TryFrom<&[u8]> for ScanResult {
+    type Error = Error;
+
+    /// Converts a scan result response into a
+    /// ScanResult, rejecting responses that are the
+    /// wrong size, are not on a 2.4GHz channel or
+    /// have an ssid without a null terminator
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let data: [u8; SCAN_RESULT_SIZE] = match data.try_into() {
+            Ok(data) => data,
+            Err(_) => return Err(HifError::InvalidFrame.into()),
+        };
+        let result = ScanResult::from(data);
+        if !(1..=14).contains(&result.channel) || !result.ssid.contains(&0) {
+            return Err(HifError::InvalidFrame.into());
+        }
+        Ok(result)
+    }
+}
+
+impl From<[u8; SCAN_RESULT_SIZE]> for ScanResult {
+    /// Converts a scan result response
+    /// into a ScanResult. The last byte
+    /// is padding
+    fn from(data: [u8; SCAN_RESULT_SIZE]) -> Self {
+        let mut bssid: [u8; 6] = [0; 6];
+        let mut ssid: [u8; MAX_SSID_LEN] = [0; MAX_SSID_LEN];
+        bssid.copy_from_slice(&data[4..10]);
+        ssid.copy_from_slice(&data[10..43]);
+        ScanResult {
+            index: data[0],
+            rssi: data[1] as i8,
+            auth_type: data[2],
+            channel: data[3],
+            bssid: MacAddress(bssid),
+            ssid,
+        }
+    }
+}
+
+/// Details of the current connection. The firmware
+/// doesn't pass on the beacon or dtim interval of
+/// the access point here or in scan results
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionInfo {
+    /// Null terminated ssid of the network
+    pub ssid: [u8; MAX_SSID_LEN],
+    /// Security type of the network
+    pub auth_type: u8,
+    /// Ip address of the Atwinc1500
+    pub ip: Ipv4Addr,
+    /// Mac address of the access point
+    pub bssid: MacAddress,
+    /// Received signal strength in dBm
+    pub rssi: i8,
+    /// Channel the Atwinc1500 is on
+    pub channel: u8,
+}
+
+impl ConnectionInfo {
+    /// Gets the ssid without the null terminator
+    pub fn ssid_bytes(&self) -> &[u8] {
+        let len = self
+            .ssid
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(MAX_SSID_LEN);
+        &self.ssid[..len]
+    }
+
+    /// Gets the signal quality as a percentage
+    /// from 0 to 100. See rssi_to_quality
+    pub fn quality(&self) -> u8 {
+        rssi_to_quality(self.rssi)
+    }
+}
+
+impl From<[u8; CONN_INFO_SIZE]> for ConnectionInfo {
+    /// Converts a connection info response
+    /// into a ConnectionInfo. The last two
+    /// bytes are padding
+    fn from(data: [u8; CONN_INFO_SIZE]) -> Self {
+        let mut ssid: [u8; MAX_SSID_LEN] = [0; MAX_SSID_LEN];
+        let mut bssid: [u8; 6] = [0; 6];
+        ssid.copy_from_slice(&data[0..33]);
+        bssid.copy_from_slice(&data[38..44]);
+        ConnectionInfo {
+            ssid,
+            auth_type: data[33],
+            ip: Ipv4Addr::new(data[34], data[35], data[36], data[37]),
+            bssid: MacAddress(bssid),
+            rssi: data[44] as i8,
+            channel: data[45],
+        }
+    }
+}
+
+/// Connection status of the Atwinc1500
+/// as tracked by the driver
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub enum Status {
+    /// Not connected to a network
+    Disconnected,
+    /// A connection request has been sent
+    /// and no response has been received
+    Connecting,
+    /// Connected to a network
+    Connected,
+    /// The last connection request failed
+    ConnectionFailed,
+}
+
+/// Summary of the connection health
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub struct LinkHealth {
+    /// Connection status
+    pub status: Status,
+    /// Last rssi received with get_current_rssi
+    pub rssi: Option<i8>,
+    /// Milliseconds since the connection was made,
+    /// as counted by tick. None when not connected
+    pub uptime_ms: Option<u64>,
+    /// Number of times the connection has dropped
+    pub reconnect_count: u32,
+}
+
+/// Number of scan results that fit in a buffer
+/// compared to the number of networks found
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub struct ScanCount {
+    /// Number of scan results to read
+    pub count: u8,
+    /// Number of networks found in the last scan
+    pub total: u8,
+}
+
+impl ScanCount {
+    /// Clamps the number of networks found
+    /// to the capacity of a buffer
+    pub fn new(total: u8, capacity: usize) -> Self {
+        Self {
+            count: (total as usize).min(capacity) as u8,
+            total,
+        }
+    }
+
+    /// Returns true if some scan results
+    /// did not fit in the buffer
+    pub fn is_truncated(&self) -> bool {
+        self.count < self.total
+    }
+}
+
+/// Error codes sent by the Atwinc1500
+/// along with a connection state change
+#[cfg_attr(
+    target_os = "none",
+    derive(Copy, Clone, Eq, PartialEq, Debug, defmt::Format)
+)]
+#[cfg_attr(not(target_os = "none"), derive(Copy, Clone, Eq, PartialEq, Debug))]
+pub enum StateChangeErrorCode {
+    /// No error
+    NoError = 0,
+    /// Failed to find the network
+    ScanFail = 1,
+    /// Failed to join the network
+    JoinFail = 2,
+    /// Failed to authenticate with the network
+    AuthFail = 3,
+    /// Failed to associate with the network
+    AssocFail = 4,
+    /// A connection attempt is already in progress
+    ConnInProgress = 5,
+    /// Error code not recognized by the driver
+    Unknown,
+}
+
+impl From<u8> for StateChangeErrorCode {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => StateChangeErrorCode::NoError,
+            1 => StateChangeErrorCode::ScanFail,
+            2 => StateChangeErrorCode::JoinFail,
+            3 => StateChangeErrorCode::AuthFail,
+            4 => StateChangeErrorCode::AssocFail,
+            5 => StateChangeErrorCode::ConnInProgress,
+            _ => StateChangeErrorCode::Unknown,
+        }
+    }
+}
+
+/// Connection state change sent
+/// by the Atwinc1500
+#[derive(Copy, Clone, Debug)]
+pub struct StateChange {
+    /// True if the Atwinc1500 is now connected
+    pub connected: bool,
+    /// The reason for the state change
+    pub error_code: StateChangeErrorCode,
+}
+
+impl From<[u8; 4]> for StateChange {
+    /// Converts the state change response
+    /// into a StateChange. The last two
+    /// bytes are padding
+    fn from(data: [u8; 4]) -> Self {
+        StateChange {
+            connected: data[0] == 1,
+            error_code: StateChangeErrorCode::from(data[1]),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for StateChange {
+    type Error = Error;
+
+    /// Converts the state change response into a
+    /// StateChange, rejecting responses that are the
+    /// wrong size or have an unknown state. Error codes
+    /// the driver doesn't know become Unknown. Roamed
+    /// (2, newer firmware) is connected
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() != 4 || data[0] > 2 {
+            return Err(HifError::InvalidFrame.into());
+        }
+        Ok(StateChange {
+            connected: data[0] != 0,
+            error_code: StateChangeErrorCode::from(data[1]),
+        })
+    }
+}
+
+/// Whether the Atwinc1500 should save the
+/// credentials of a connection to its flash
+///
+/// Saved credentials are what connect_default_network
+/// uses to reconnect, so only the last network connected
+/// with SaveToFlash can be reconnected that way. The
+/// default is DontSave
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum SaveCredentials {
+    /// Save the credentials to flash
+    SaveToFlash = 0,
+    /// Do not save the credentials
+    #[default]
+    DontSave = 1,
+}
+
 /// Configurable options used for connecting to
 /// a wireless nework
+#[derive(Clone)]
 pub struct ConnectionOptions {
     sec_type: SecurityType,
-    save_creds: u8,
+    save_creds: SaveCredentials,
     channel: Channel,
+    bssid: Option<MacAddress>,
 }
 
 /// Parameters used to connect to a wireless network
+///
+/// Connecting only borrows the parameters, so the same
+/// ConnectionParameters can be used for several connection
+/// attempts or reconnects
+#[derive(Clone)]
 pub enum ConnectionParameters {
     /// ConnectionParameters for an open network
     Open([u8; MAX_SSID_LEN], ConnectionOptions),
@@ -93,50 +649,224 @@ pub enum ConnectionParameters {
 impl ConnectionParameters {
     /// Creates connection parameters for
     /// connecting to an open wifi network
-    pub fn open(ssid: &[u8], channel: Channel, save_creds: u8) -> Self {
-        let mut ssid_arr = [0; MAX_SSID_LEN];
-        ssid_arr[..ssid.len()].copy_from_slice(ssid);
+    ///
+    /// Returns Error::SsidTooLong if the
+    /// ssid is longer than 32 bytes
+    pub fn open(ssid: &[u8], channel: Channel, save_creds: SaveCredentials) -> Result<Self, Error> {
+        let ssid_arr = ssid_to_array(ssid)?;
         let options = ConnectionOptions {
             sec_type: SecurityType::Open,
             save_creds,
             channel,
+            bssid: None,
         };
-        ConnectionParameters::Open(ssid_arr, options)
+        Ok(ConnectionParameters::Open(ssid_arr, options))
     }
 
     /// Creates WEP connection parameters
     /// for connecting to a WEP protected wifi network
-    pub fn _wep() -> Self {
-        todo!()
+    pub fn _wep() -> Result<Self, Error> {
+        Err(Error::NotImplemented)
     }
 
     /// Creates WPA PSK connection parameters
     /// for connecting to a WPA PSK protected wifi network
-    pub fn wpa_psk(ssid: &[u8], wpa_psk: &[u8], channel: Channel, save_creds: u8) -> Self {
-        let mut ssid_arr = [0; MAX_SSID_LEN];
+    ///
+    /// Returns Error::SsidTooLong if the ssid is longer than
+    /// 32 bytes or Error::PassphraseTooLong if the passphrase
+    /// is longer than 64 bytes
+    pub fn wpa_psk(
+        ssid: &[u8],
+        wpa_psk: &[u8],
+        channel: Channel,
+        save_creds: SaveCredentials,
+    ) -> Result<Self, Error> {
+        let ssid_arr = ssid_to_array(ssid)?;
         let mut wpa_psk_arr = [0; MAX_PSK_LEN];
-        ssid_arr[..ssid.len()].copy_from_slice(ssid);
+        // Leave room for the null terminator
+        if wpa_psk.len() >= MAX_PSK_LEN {
+            return Err(Error::PassphraseTooLong);
+        }
         wpa_psk_arr[..wpa_psk.len()].copy_from_slice(wpa_psk);
         let options = ConnectionOptions {
             sec_type: SecurityType::WpaPsk,
             save_creds,
             channel,
+            bssid: None,
         };
-        ConnectionParameters::WpaPsk(ssid_arr, wpa_psk_arr, options)
+        Ok(ConnectionParameters::WpaPsk(ssid_arr, wpa_psk_arr, options))
     }
 
     /// Creates WPA Enterprise connection parameters
     /// for connecting to a WPA Enterprise protected wifi network
-    pub fn _wpa_enterprise() -> Self {
-        todo!()
+    pub fn _wpa_enterprise() -> Result<Self, Error> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Restricts the connection to the access point
+    /// with this bssid, for networks where several
+    /// access points share an ssid
+    ///
+    /// The bssid is only part of the newer connection
+    /// format, so connecting with a bssid needs firmware
+    /// 19.6.1 or newer, older firmware returns
+    /// Error::UnsupportedFirmware
+    pub fn with_bssid(mut self, bssid: MacAddress) -> Self {
+        if let Some(opts) = self.options_mut() {
+            opts.bssid = Some(bssid);
+        }
+        self
+    }
+
+    /// Gets the bssid set with with_bssid
+    pub fn bssid(&self) -> Option<MacAddress> {
+        match self {
+            ConnectionParameters::Open(_, opts) => opts.bssid,
+            ConnectionParameters::WpaPsk(_, _, opts) => opts.bssid,
+            _ => None,
+        }
+    }
+
+    /// Gets the options of the security
+    /// types that can be created
+    fn options_mut(&mut self) -> Option<&mut ConnectionOptions> {
+        match self {
+            ConnectionParameters::Open(_, opts) => Some(opts),
+            ConnectionParameters::WpaPsk(_, _, opts) => Some(opts),
+            _ => None,
+        }
+    }
+
+    /// Serializes the parameters for storing them
+    /// outside of the Atwinc1500. The bytes hold the
+    /// passphrase in plain text, so encrypt them before
+    /// they are written anywhere
+    ///
+    /// Format (CONNECTION_BYTES_VERSION 2):
+    /// * 0: format version
+    /// * 1: security type, 1 open or 2 WPA PSK
+    /// * 2: channel
+    /// * 3: save credentials
+    /// * 4..37: null terminated ssid
+    /// * 37..102: null terminated passphrase, zeros
+    ///   for an open network
+    /// * 102: 1 if a bssid is set, otherwise 0
+    /// * 103..109: bssid, zeros if not set
+    ///
+    /// Version 1 is the first 102 bytes
+    ///
+    /// Returns Error::NotImplemented for the
+    /// security types that can't be created yet
+    pub fn to_bytes(&self) -> Result<[u8; CONNECTION_BYTES_SIZE], Error> {
+        let mut data = [0; CONNECTION_BYTES_SIZE];
+        let (ssid, opts) = match self {
+            ConnectionParameters::Open(ssid, opts) => (ssid, opts),
+            ConnectionParameters::WpaPsk(ssid, pass, opts) => {
+                data[37..102].copy_from_slice(pass);
+                (ssid, opts)
+            }
+            _ => return Err(Error::NotImplemented),
+        };
+        data[0] = CONNECTION_BYTES_VERSION;
+        data[1] = opts.sec_type as u8;
+        data[2] = opts.channel as u8;
+        data[3] = opts.save_creds as u8;
+        data[4..37].copy_from_slice(ssid);
+        if let Some(bssid) = opts.bssid {
+            data[102] = 1;
+            data[103..109].copy_from_slice(&bssid.0);
+        }
+        Ok(data)
+    }
+
+    /// Creates parameters from bytes made by to_bytes.
+    /// Every version up to CONNECTION_BYTES_VERSION is
+    /// accepted, so bytes saved by an older release of
+    /// the driver can still be read
+    ///
+    /// Returns Error::InvalidConnectionBytes if the bytes
+    /// are the wrong size, a newer version or hold a value
+    /// that is out of range
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let size = match data.first() {
+            Some(1) => CONNECTION_BYTES_V1_SIZE,
+            Some(2) => CONNECTION_BYTES_SIZE,
+            _ => return Err(Error::InvalidConnectionBytes),
+        };
+        if data.len() != size {
+            return Err(Error::InvalidConnectionBytes);
+        }
+        let bssid = match data.get(102) {
+            None | Some(0) => None,
+            Some(1) => {
+                let mut bssid = [0; 6];
+                bssid.copy_from_slice(&data[103..109]);
+                Some(MacAddress(bssid))
+            }
+            Some(_) => return Err(Error::InvalidConnectionBytes),
+        };
+        let channel = Channel::try_from(data[2]).map_err(|_| Error::InvalidConnectionBytes)?;
+        let save_creds = match data[3] {
+            0 => SaveCredentials::SaveToFlash,
+            1 => SaveCredentials::DontSave,
+            _ => return Err(Error::InvalidConnectionBytes),
+        };
+        // The ssid and passphrase must be null terminated
+        let ssid = &data[4..37];
+        let pass = &data[37..102];
+        if ssid[MAX_SSID_LEN - 1] != 0 || pass[MAX_PSK_LEN - 1] != 0 {
+            return Err(Error::InvalidConnectionBytes);
+        }
+        let mut params = match data[1] {
+            1 => Self::open(trim_null(ssid), channel, save_creds)?,
+            2 => Self::wpa_psk(trim_null(ssid), trim_null(pass), channel, save_creds)?,
+            _ => return Err(Error::InvalidConnectionBytes),
+        };
+        if let Some(opts) = params.options_mut() {
+            opts.bssid = bssid;
+        }
+        Ok(params)
     }
 }
 
-impl From<ConnectionParameters> for OldConnection {
+/// Size of ConnectionParameters serialized with to_bytes
+pub const CONNECTION_BYTES_SIZE: usize = 109;
+/// Size of version 1 of the format, before the bssid
+const CONNECTION_BYTES_V1_SIZE: usize = 102;
+/// Format version written by to_bytes
+pub const CONNECTION_BYTES_VERSION: u8 = 2;
+
+/// Converts a received signal strength in dBm to
+/// a quality percentage. -50 dBm and stronger is 100%,
+/// -100 dBm and weaker is 0%, and the range between
+/// is linear
+pub fn rssi_to_quality(rssi: i8) -> u8 {
+    let rssi = (rssi as i16).clamp(-100, -50);
+    (2 * (rssi + 100)) as u8
+}
+
+/// Gets a null terminated field without the terminator
+fn trim_null(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+/// Copies an ssid into a null terminated array
+fn ssid_to_array(ssid: &[u8]) -> Result<[u8; MAX_SSID_LEN], Error> {
+    let mut ssid_arr = [0; MAX_SSID_LEN];
+    // Leave room for the null terminator
+    if ssid.len() >= MAX_SSID_LEN {
+        return Err(Error::SsidTooLong);
+    }
+    ssid_arr[..ssid.len()].copy_from_slice(ssid);
+    Ok(ssid_arr)
+}
+
+impl From<&ConnectionParameters> for OldConnection {
     /// Easily convert ConnectionParameters to the old
     /// wifi connection format
-    fn from(connection: ConnectionParameters) -> Self {
-        let mut conn_header: OldConnection = [0; 106];
+    fn from(connection: &ConnectionParameters) -> Self {
+        let mut conn_header: OldConnection = [0; 108];
         match connection {
             ConnectionParameters::Open(ssid, opts) => {
                 conn_header[65] = opts.sec_type as u8;
@@ -144,22 +874,20 @@ impl From<ConnectionParameters> for OldConnection {
                 conn_header[67] = 0;
                 conn_header[68] = opts.channel as u8;
                 conn_header[69] = 0;
-                conn_header[70..103].copy_from_slice(&ssid);
-                conn_header[103] = opts.save_creds;
-                conn_header[104] = 0;
-                conn_header[105] = 0;
+                conn_header[70..103].copy_from_slice(ssid);
+                conn_header[103] = opts.save_creds as u8;
+                // padding (4 bytes)
             }
             ConnectionParameters::WpaPsk(ssid, pass, opts) => {
-                conn_header[0..MAX_PSK_LEN].copy_from_slice(&pass);
+                conn_header[0..MAX_PSK_LEN].copy_from_slice(pass);
                 conn_header[65] = opts.sec_type as u8;
                 conn_header[66] = 0;
                 conn_header[67] = 0;
                 conn_header[68] = opts.channel as u8;
                 conn_header[69] = 0;
-                conn_header[70..103].copy_from_slice(&ssid);
-                conn_header[103] = opts.save_creds;
-                conn_header[104] = 0;
-                conn_header[105] = 0;
+                conn_header[70..103].copy_from_slice(ssid);
+                conn_header[103] = opts.save_creds as u8;
+                // padding (4 bytes)
             }
             ConnectionParameters::_Wep() => {}
             ConnectionParameters::_WpaEnterprise() => {}
@@ -168,20 +896,40 @@ impl From<ConnectionParameters> for OldConnection {
     }
 }
 
-impl From<ConnectionParameters> for NewConnection {
+impl From<&ConnectionParameters> for NewConnection {
     /// Easily convert ConnectionParameters to the new
     /// wifi connection format
-    fn from(connection: ConnectionParameters) -> Self {
-        let mut _conn_header: NewConnection = ([0; 48], [0; 108]);
-        match connection {
-            ConnectionParameters::Open(_ssid, _opts) => {}
-            ConnectionParameters::WpaPsk(_ssid, _pass, _opts) => {}
-            ConnectionParameters::_Wep() => {
-                /* This is an error, WEP was deprecated for
-                 * the new connection model */
+    fn from(connection: &ConnectionParameters) -> Self {
+        let (mut conn_header, mut psk): NewConnection = ([0; 48], [0; 100]);
+        let (ssid, opts, auth_size) = match connection {
+            ConnectionParameters::Open(ssid, opts) => (ssid, opts, 0),
+            ConnectionParameters::WpaPsk(ssid, pass, opts) => {
+                // passphrase length, passphrase (64 bytes), psk
+                // (32 bytes), psk calculated, padding (2 bytes)
+                psk[0] = trim_null(pass).len() as u8;
+                psk[1..MAX_PSK_LEN].copy_from_slice(&pass[..MAX_PSK_LEN - 1]);
+                (ssid, opts, psk.len())
             }
-            ConnectionParameters::_WpaEnterprise() => {}
+            /* WEP was deprecated for the new connection model */
+            ConnectionParameters::_Wep() => return (conn_header, psk),
+            ConnectionParameters::_WpaEnterprise() => return (conn_header, psk),
+        };
+        // credential size (2 bytes), store flags, channel,
+        // bssid (6 bytes), security type, options
+        let cred_size = (CONN_CRED_HEADER_SIZE + auth_size) as u16;
+        conn_header[0..2].copy_from_slice(&cred_size.to_le_bytes());
+        if opts.save_creds == SaveCredentials::SaveToFlash {
+            conn_header[2] = CRED_STORE_FLAG;
+        }
+        conn_header[3] = opts.channel as u8;
+        if let Some(bssid) = opts.bssid {
+            conn_header[4..10].copy_from_slice(&bssid.0);
+            conn_header[11] = CONN_BSSID_FLAG;
         }
-        _conn_header
+        conn_header[10] = opts.sec_type as u8;
+        // ssid length, ssid (33 bytes), padding (2 bytes)
+        conn_header[12] = trim_null(ssid).len() as u8;
+        conn_header[13..46].copy_from_slice(ssid);
+        (conn_header, psk)
     }
 }
@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod gpio_unit_tests {
+    use atwinc1500::error::Error;
+    use atwinc1500::gpio::{AtwincGpio, GpioDirection, GpioPull, GpioValue};
+    use atwinc1500::mock::MockAtwinc;
+    use atwinc1500::registers;
+
+    #[test]
+    fn all_gpio_in_order() {
+        let pins: Vec<u8> = AtwincGpio::all().map(|gpio| gpio as u8).collect();
+        assert_eq!(pins, [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn read_all_gpio() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc
+            .set_gpio_direction(AtwincGpio::Gpio4, GpioDirection::Output)
+            .unwrap();
+        mock.set_register(registers::GPIO_GET_VAL_REG, 1 << 6);
+        let snapshot = atwinc.read_all_gpio().unwrap();
+        for gpio in AtwincGpio::all() {
+            let expected = match gpio {
+                AtwincGpio::Gpio4 => GpioDirection::Output,
+                _ => GpioDirection::Input,
+            };
+            assert_eq!(snapshot.direction(gpio), expected);
+        }
+        assert_eq!(snapshot.value(AtwincGpio::Gpio6), GpioValue::High);
+        assert_eq!(snapshot.value(AtwincGpio::Gpio3), GpioValue::Low);
+    }
+
+    #[test]
+    fn set_gpio_pull() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc
+            .set_gpio_pull(AtwincGpio::Gpio3, GpioPull::None)
+            .unwrap();
+        atwinc
+            .set_gpio_pull(AtwincGpio::Gpio5, GpioPull::None)
+            .unwrap();
+        atwinc
+            .set_gpio_pull(AtwincGpio::Gpio5, GpioPull::Up)
+            .unwrap();
+        assert_eq!(mock.register(registers::PULLUP_CTRL_REG), 1 << 3);
+        assert!(matches!(
+            atwinc.set_gpio_pull(AtwincGpio::Gpio6, GpioPull::Up),
+            Err(Error::InvalidGpio)
+        ));
+    }
+}
@@ -0,0 +1,1516 @@
+#[cfg(test)]
+mod hif_unit_tests {
+    use atwinc1500::dns::DEFAULT_DNS_CACHE_TTL_MS;
+    use atwinc1500::error::{Error, HifError};
+    use atwinc1500::event::Event;
+    use atwinc1500::mock::MockAtwinc;
+    use atwinc1500::registers;
+    use atwinc1500::socket::{
+        sockaddr_to_bytes, CipherSuite, PingResult, SocketCommand, MAX_KEEPALIVE_INTERVAL_MS,
+        MAX_SOCKETS, PING_ERR_SUCCESS, SOCKET_BUFFER_MAX_LENGTH, SOCKET_RX_BUFFER_SIZE,
+        TCP_SOCK_MAX,
+    };
+    use atwinc1500::types::{FirmwareVersion, InitConfig, MacAddress};
+    use atwinc1500::wifi::{
+        Channel, ConnectionParameters, SaveCredentials, ScanOptions, StateChangeErrorCode, Status,
+        TxPower, MAX_MULTICAST_FILTERS, MAX_SCAN_RESULTS,
+    };
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    // Group ids and opcodes from the Atmel driver
+    const GID_WIFI: u8 = 1;
+    const GID_IP: u8 = 2;
+    const REQ_GET_CONN_INFO: u8 = 5;
+    const RESP_CONN_INFO: u8 = 6;
+    const REQ_SCAN: u8 = 16;
+    const REQ_SET_SCAN_OPTION: u8 = 20;
+    const REQ_SET_TX_POWER: u8 = 23;
+    const REQ_SET_MAC_MCAST: u8 = 30;
+    const RESP_SCAN_DONE: u8 = 17;
+    const REQ_SCAN_RESULT: u8 = 18;
+    const RESP_SCAN_RESULT: u8 = 19;
+    const REQ_CONNECT: u8 = 40;
+    const REQ_CONN: u8 = 59;
+    const REQ_DISCONNECT: u8 = 43;
+    const RESP_CON_STATE_CHANGED: u8 = 44;
+    const REQ_DHCP_CONF: u8 = 50;
+    const REQ_GET_PRNG: u8 = 31;
+    const RESP_GET_PRNG: u8 = 32;
+
+    fn open_network() -> ConnectionParameters {
+        ConnectionParameters::open(b"network", Channel::Any, SaveCredentials::default()).unwrap()
+    }
+
+    #[test]
+    fn driver_initializes() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let version = atwinc.get_firmware_version().unwrap();
+        assert_eq!(version.0, [19, 6, 1]);
+        assert_eq!(atwinc.get_spi_protocol_config().unwrap() & 0xc, 0);
+        assert_eq!(atwinc.get_init_state().unwrap(), 0);
+    }
+
+    #[test]
+    fn supported_features_follow_firmware() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let features = atwinc.supported_features();
+        assert_eq!(features.firmware_version, FirmwareVersion([19, 6, 1]));
+        assert!(features.tls);
+        assert!(features.erase_saved_credentials);
+        assert!(features.tcp_keepalive);
+        // 19.5.2
+        mock.set_register(registers::NMI_REV_REG, 0x1352);
+        atwinc.recover().unwrap();
+        let features = atwinc.supported_features();
+        assert!(features.tls);
+        assert!(!features.new_connection_format);
+        assert!(!features.tcp_keepalive);
+        assert_eq!(
+            atwinc.erase_saved_credentials(),
+            Err(Error::UnsupportedFirmware)
+        );
+    }
+
+    #[test]
+    fn driver_writes_init_config() {
+        let mock = MockAtwinc::new();
+        let config = InitConfig {
+            version_info: InitConfig::make_version_info(
+                FirmwareVersion([19, 7, 3]),
+                FirmwareVersion([19, 3, 0]),
+            ),
+            conf: 0x100,
+            ..Default::default()
+        };
+        assert_eq!(config.version_info, 0x13731330);
+        mock.driver_with_config(false, config).unwrap();
+        assert_eq!(mock.register(registers::rNMI_GP_REG_1), 0x100);
+    }
+
+    #[test]
+    fn reset_pulse_uses_config() {
+        let default = MockAtwinc::new();
+        default.driver(false).unwrap();
+        let fast = MockAtwinc::new();
+        let config = InitConfig {
+            reset_low_ms: 1,
+            reset_high_ms: 10,
+            ..Default::default()
+        };
+        fast.driver_with_config(false, config).unwrap();
+        assert_eq!(default.elapsed_ms() - fast.elapsed_ms(), 2000 - 11);
+    }
+
+    #[test]
+    fn firmware_start_retries_uses_config() {
+        let default = MockAtwinc::new();
+        default.set_firmware_starts(false);
+        assert!(matches!(default.driver(false), Err(Error::InitTimeout)));
+        let fast = MockAtwinc::new();
+        fast.set_firmware_starts(false);
+        let config = InitConfig {
+            firmware_start_retries: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            fast.driver_with_config(false, config),
+            Err(Error::InitTimeout)
+        ));
+        assert!(fast.elapsed_ms() < default.elapsed_ms());
+    }
+
+    #[test]
+    fn min_driver_version() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(
+            atwinc.get_min_driver_version().unwrap(),
+            FirmwareVersion([19, 3, 0])
+        );
+        // firmware 19.7.0 needing driver 19.5.0
+        mock.set_register(registers::NMI_REV_REG, 0x13501370);
+        assert_eq!(atwinc.recover(), Err(Error::DriverVersionMismatch));
+        let config = InitConfig {
+            version_info: InitConfig::make_version_info(
+                FirmwareVersion([19, 7, 0]),
+                FirmwareVersion([19, 5, 0]),
+            ),
+            ..Default::default()
+        };
+        assert!(mock.driver_with_config(false, config).is_ok());
+    }
+
+    #[test]
+    fn firmware_not_starting_is_init_timeout() {
+        let mock = MockAtwinc::new();
+        mock.set_firmware_starts(false);
+        assert!(matches!(mock.driver(false), Err(Error::InitTimeout)));
+        // The firmware never replaced the driver version info
+        assert_eq!(
+            mock.register(registers::NMI_STATE_REG),
+            InitConfig::DEFAULT_VERSION_INFO
+        );
+    }
+
+    #[test]
+    fn recover_reinitializes() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+        atwinc.recover().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+        assert_eq!(atwinc.get_init_state().unwrap(), 0);
+        assert_eq!(atwinc.get_firmware_version().unwrap().0, [19, 6, 1]);
+    }
+
+    #[test]
+    fn soft_reset_uses_global_reset() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        atwinc.soft_reset().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+        assert_eq!(mock.register(registers::rNMI_GLB_RESET), 0);
+        assert_eq!(atwinc.get_init_state().unwrap(), 0);
+        assert_eq!(atwinc.get_spi_protocol_config().unwrap() & 0xc, 0);
+    }
+
+    #[test]
+    fn halt_cpu_clears_cpu_enable() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.set_register(registers::rNMI_GLB_RESET, 0x7ff);
+        atwinc.halt_cpu().unwrap();
+        assert_eq!(mock.register(registers::rNMI_BOOT_RESET_MUX) & 1, 1);
+        assert_eq!(mock.register(registers::rNMI_GLB_RESET), 0x3ff);
+    }
+
+    #[test]
+    fn flash_info() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.halt_cpu().unwrap();
+        let info = atwinc.get_flash_info().unwrap();
+        assert_eq!(info.size_mbit, 8);
+        assert_eq!(info.size_bytes(), 1024 * 1024);
+        assert!(info.is_8mbit());
+        mock.set_flash_id(0x001340ef);
+        assert!(!atwinc.get_flash_info().unwrap().is_8mbit());
+        mock.set_flash_id(0xffffffff);
+        assert_eq!(atwinc.get_flash_info(), Err(Error::FlashNotDetected));
+        atwinc.recover().unwrap();
+    }
+
+    #[test]
+    fn last_disconnect_reason() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(atwinc.get_last_disconnect_reason(), None);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 3, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(
+            atwinc.get_last_disconnect_reason(),
+            Some(StateChangeErrorCode::AuthFail)
+        );
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(
+            atwinc.get_last_disconnect_reason(),
+            Some(StateChangeErrorCode::NoError)
+        );
+    }
+
+    #[test]
+    fn disconnect_with_unknown_error_code() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 6, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+        assert_eq!(atwinc.get_reconnect_count(), 1);
+        assert_eq!(
+            atwinc.get_last_disconnect_reason(),
+            Some(StateChangeErrorCode::Unknown)
+        );
+    }
+
+    #[test]
+    fn deinitialize_disconnects_and_stops() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        mock.set_register(registers::rNMI_GLB_RESET, 0x7ff);
+        mock.take_sent_frames();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        atwinc.deinitialize(false).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].op, REQ_DISCONNECT);
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+        assert_eq!(mock.register(registers::NMI_INTR_REG_BASE) & 0x10000, 0);
+        assert_eq!(mock.register(registers::rNMI_GLB_RESET), 0x3ff);
+        atwinc.recover().unwrap();
+        assert_eq!(atwinc.get_init_state().unwrap(), 0);
+    }
+
+    #[test]
+    fn default_version_info() {
+        assert_eq!(
+            InitConfig::make_version_info(FirmwareVersion([19, 5, 2]), FirmwareVersion([19, 3, 0])),
+            InitConfig::DEFAULT_VERSION_INFO
+        );
+    }
+
+    #[test]
+    fn connect_sends_frame() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.connect_network(&open_network()).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].gid, GID_WIFI);
+        assert_eq!(frames[0].op, REQ_CONNECT);
+        assert_eq!(&frames[0].data[70..77], b"network");
+        assert!(matches!(atwinc.get_status(), Status::Connecting));
+    }
+
+    #[test]
+    fn connect_with_bssid() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let bssid = MacAddress([1, 2, 3, 4, 5, 6]);
+        let params = open_network().with_bssid(bssid);
+        atwinc.connect_network(&params).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].op, REQ_CONN);
+        assert_eq!(frames[0].data.len(), 48);
+        // credential size, flags, channel, bssid, open, bssid option
+        assert_eq!(frames[0].data[..4], [12, 0, 0, 255]);
+        assert_eq!(frames[0].data[4..10], bssid.0);
+        assert_eq!(frames[0].data[10..12], [1, 1]);
+        assert_eq!(frames[0].data[12], 7);
+        assert_eq!(&frames[0].data[13..20], b"network");
+        let params = ConnectionParameters::wpa_psk(
+            b"network",
+            b"password",
+            Channel::Any,
+            SaveCredentials::default(),
+        )
+        .unwrap()
+        .with_bssid(bssid);
+        atwinc.connect_network(&params).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].data.len(), 148);
+        assert_eq!(frames[0].data[..2], [112, 0]);
+        assert_eq!(frames[0].data[4..10], bssid.0);
+        assert_eq!(frames[0].data[48], 8);
+        assert_eq!(&frames[0].data[49..57], b"password");
+    }
+
+    #[test]
+    fn connect_with_retries_keeps_bssid() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let bssid = MacAddress([1, 2, 3, 4, 5, 6]);
+        let params = open_network().with_bssid(bssid);
+        assert_eq!(
+            atwinc.connect_network_with_retries(&params, 1, 0),
+            Err(Error::ConnectionTimeout)
+        );
+        let frames = mock.take_sent_frames();
+        let connects: Vec<_> = frames.iter().filter(|f| f.op == REQ_CONN).collect();
+        assert_eq!(connects.len(), 2);
+        assert!(connects.iter().all(|f| f.data[4..10] == bssid.0));
+        assert!(frames.iter().all(|f| f.op != REQ_CONNECT));
+    }
+
+    #[test]
+    fn connect_with_retries_ignores_stale_disconnect() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        // The first attempt times out, and the firmware
+        // confirms its disconnect during the retry
+        mock.push_reply(
+            REQ_DISCONNECT,
+            GID_WIFI,
+            RESP_CON_STATE_CHANGED,
+            &[0, 0, 0, 0],
+        );
+        // The retry is left waiting rather than failed
+        assert_eq!(
+            atwinc.connect_network_with_retries(&open_network(), 1, 5),
+            Err(Error::ConnectionTimeout)
+        );
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.iter().filter(|f| f.op == REQ_CONNECT).count(), 2);
+        assert_eq!(frames.last().unwrap().op, REQ_DISCONNECT);
+        // A later disconnect is handled normally
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+    }
+
+    #[test]
+    fn tx_power() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(atwinc.get_tx_power(), None);
+        atwinc.set_tx_power(TxPower::Low).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].op, REQ_SET_TX_POWER);
+        assert_eq!(frames[0].data, [3, 0, 0, 0]);
+        assert_eq!(atwinc.get_tx_power(), Some(TxPower::Low));
+        atwinc.recover().unwrap();
+        assert_eq!(atwinc.get_tx_power(), None);
+    }
+
+    #[test]
+    fn debug_state() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.request_network_scan(Channel::Any).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, 3, b"abc"),
+        );
+        atwinc.handle_events().unwrap();
+        let state = atwinc.debug_state();
+        assert!(state.scan_in_progress);
+        assert!(matches!(state.status, Status::Disconnected));
+        assert_eq!(state.queued_events, 1);
+        assert_eq!(state.next_session, 1);
+        let debug_socket = state.sockets[socket.handle() as usize].unwrap();
+        assert_eq!(debug_socket.buffered, 3);
+        assert!(!debug_socket.connected);
+        assert!(state.sockets[1].is_none());
+    }
+
+    #[test]
+    fn multicast_filters() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let mdns = MacAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]);
+        let other = MacAddress([0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]);
+        atwinc.add_multicast_filter(mdns).unwrap();
+        atwinc.add_multicast_filter(other).unwrap();
+        atwinc.add_multicast_filter(mdns).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].op, REQ_SET_MAC_MCAST);
+        assert_eq!(frames[0].data, [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb, 1, 0]);
+        assert_eq!(atwinc.get_multicast_filters(), [mdns, other]);
+        atwinc.remove_multicast_filter(mdns).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].data[6], 0);
+        assert_eq!(atwinc.get_multicast_filters(), [other]);
+        for i in 0..MAX_MULTICAST_FILTERS as u8 - 1 {
+            atwinc
+                .add_multicast_filter(MacAddress([0x01, 0, 0x5e, 0, 1, i]))
+                .unwrap();
+        }
+        assert_eq!(
+            atwinc.add_multicast_filter(mdns),
+            Err(Error::IndexOutOfRange)
+        );
+        atwinc.recover().unwrap();
+        assert!(atwinc.get_multicast_filters().is_empty());
+    }
+
+    #[test]
+    fn send_info() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(atwinc.get_last_send_info(), None);
+        atwinc.connect_network(&open_network()).unwrap();
+        let info = atwinc.get_last_send_info().unwrap();
+        assert_ne!(info.address, 0);
+        assert_eq!(info.length as usize, 8 + 108);
+        assert_eq!(info.allocation_polls, 0);
+    }
+
+    #[test]
+    fn state_change_connects() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.connect_network(&open_network()).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(!mock.has_pending_frames());
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+    }
+
+    #[test]
+    fn state_change_fails_connection() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.connect_network(&open_network()).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 1, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::ConnectionFailed));
+    }
+
+    #[test]
+    fn dhcp_conf_and_disconnect_events() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        mock.push_frame(
+            GID_WIFI,
+            REQ_DHCP_CONF,
+            &[
+                192, 168, 1, 42, 192, 168, 1, 1, 8, 8, 8, 8, 255, 255, 255, 0, 0x80, 0x51, 0x01,
+                0x00,
+            ],
+        );
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::IpConfigured));
+        let config = atwinc.get_ip_config().unwrap();
+        assert_eq!(config.ip, Ipv4Addr::new(192, 168, 1, 42));
+        assert_eq!(config.lease_time, 86400);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::DhcpLost));
+        assert!(atwinc.get_ip_config().is_none());
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+    }
+
+    #[test]
+    fn scan_and_read_result() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.request_network_scan(Channel::Any).unwrap();
+        assert!(atwinc.is_scan_in_progress());
+        assert!(matches!(
+            atwinc.request_network_scan(Channel::Any),
+            Err(Error::ScanInProgress)
+        ));
+        mock.push_frame(GID_WIFI, RESP_SCAN_DONE, &[2, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::ScanDone { num_ap: 2 }));
+        assert_eq!(atwinc.get_num_ap(), 2);
+
+        atwinc.request_scan_result(1).unwrap();
+        assert!(matches!(
+            atwinc.request_scan_result(2),
+            Err(Error::IndexOutOfRange)
+        ));
+        let mut result = [0; 44];
+        result[0] = 1; // index
+        result[1] = (-50_i8) as u8; // rssi
+        result[3] = 6; // channel
+        result[10..14].copy_from_slice(b"home");
+        mock.push_frame(GID_WIFI, RESP_SCAN_RESULT, &result);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::ScanResult { index: 1 }));
+        let result = atwinc.get_scan_result().unwrap();
+        assert_eq!(result.index, 1);
+        assert_eq!(result.rssi, -50);
+        assert!(matches!(result.channel_enum(), Channel::Ch6));
+        assert_eq!(&result.ssid[0..4], b"home");
+
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].op, REQ_SCAN);
+        assert_eq!(frames[0].data, [255, 0, 0, 0]);
+        assert_eq!(frames[1].op, REQ_SCAN_RESULT);
+        assert_eq!(frames[1].data[0], 1);
+    }
+
+    #[test]
+    fn connect_blocking_times_out() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let start = mock.elapsed_ms();
+        assert!(matches!(
+            atwinc.connect_network_blocking(&open_network(), 50),
+            Err(Error::ConnectionTimeout)
+        ));
+        assert!(mock.elapsed_ms() - start >= 50);
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.last().unwrap().op, REQ_DISCONNECT);
+        assert!(matches!(atwinc.get_status(), Status::Disconnected));
+    }
+
+    #[test]
+    fn short_frame_is_address_mismatch() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        // A scan result is 44 bytes
+        mock.push_frame(GID_WIFI, RESP_SCAN_RESULT, &[0, 0, 0, 0]);
+        assert!(matches!(
+            atwinc.handle_events(),
+            Err(Error::HifError(HifError::AddressMismatch))
+        ));
+        assert!(atwinc.get_scan_result().is_none());
+        atwinc.hif_recover().unwrap();
+        assert!(!mock.has_pending_frames());
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+    }
+
+    #[test]
+    fn disable_and_enable_events() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.disable_events().unwrap();
+        assert_eq!(mock.register(registers::NMI_INTR_REG_BASE) & 0x10000, 0);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.enable_events().unwrap();
+        assert_ne!(mock.register(registers::NMI_INTR_REG_BASE) & 0x10000, 0);
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+    }
+
+    #[test]
+    fn interrupt_status_and_clear() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(atwinc.get_interrupt_status().unwrap(), 0);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        assert_eq!(atwinc.get_interrupt_status().unwrap(), (12 << 2) | 1);
+        atwinc.clear_interrupt().unwrap();
+        assert_eq!(atwinc.get_interrupt_status().unwrap(), 12 << 2);
+        // The frame is still waiting until reception is finished
+        assert!(mock.has_pending_frames());
+        atwinc.hif_recover().unwrap();
+        assert!(!mock.has_pending_frames());
+    }
+
+    #[test]
+    fn drain_all_scan_results() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.request_network_scan(Channel::Any).unwrap();
+        mock.push_frame(GID_WIFI, RESP_SCAN_DONE, &[3, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        mock.take_sent_frames();
+        let scan_count = atwinc.request_all_scan_results().unwrap();
+        assert!(!scan_count.is_truncated());
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.op == REQ_SCAN_RESULT));
+        for index in 0..3 {
+            let mut result = [0; 44];
+            result[0] = index;
+            result[3] = index + 1; // channel
+            mock.push_frame(GID_WIFI, RESP_SCAN_RESULT, &result);
+        }
+        // One call handles every pending frame
+        atwinc.handle_events().unwrap();
+        assert!(!mock.has_pending_frames());
+        let results = atwinc.get_scan_results();
+        assert_eq!(results.len(), 3);
+        for (index, result) in results.iter().enumerate() {
+            let result = result.unwrap();
+            assert_eq!(result.index as usize, index);
+            assert_eq!(result.channel as usize, index + 1);
+        }
+    }
+
+    #[test]
+    fn quick_scan_sets_options() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        assert_eq!(
+            atwinc.quick_scan_channel(Channel::Any),
+            Err(Error::InvalidChannel)
+        );
+        atwinc.quick_scan_channel(Channel::Ch6).unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].op, REQ_SET_SCAN_OPTION);
+        assert_eq!(frames[0].data, [1, 10, 1, -99_i8 as u8]);
+        assert_eq!(frames[1].op, REQ_SCAN);
+        assert_eq!(frames[1].data[0], 6);
+        let options = ScanOptions {
+            slot_time_ms: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            atwinc.set_scan_options(&options),
+            Err(Error::InvalidScanOptions)
+        );
+    }
+
+    #[test]
+    fn scan_while_connected_keeps_status() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        atwinc.request_network_scan(Channel::Any).unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+        mock.push_frame(GID_WIFI, RESP_SCAN_DONE, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+        assert_eq!(atwinc.get_reconnect_count(), 0);
+    }
+
+    #[test]
+    fn scan_count_truncated() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        atwinc.request_network_scan(Channel::Any).unwrap();
+        mock.push_frame(GID_WIFI, RESP_SCAN_DONE, &[30, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        let scan_count = atwinc.get_scan_count(8);
+        assert_eq!(scan_count.count, 8);
+        assert_eq!(scan_count.total, 30);
+        assert!(scan_count.is_truncated());
+        mock.take_sent_frames();
+        let scan_count = atwinc.request_all_scan_results().unwrap();
+        assert_eq!(scan_count.count as usize, MAX_SCAN_RESULTS);
+        assert!(scan_count.is_truncated());
+        assert_eq!(mock.take_sent_frames().len(), MAX_SCAN_RESULTS);
+    }
+
+    #[test]
+    fn link_health() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        assert_eq!(atwinc.get_link_health().uptime_ms, None);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        atwinc.tick(1500);
+        let health = atwinc.get_link_health();
+        assert!(matches!(health.status, Status::Connected));
+        assert_eq!(health.uptime_ms, Some(1500));
+        assert_eq!(health.reconnect_count, 0);
+        assert_eq!(atwinc.get_last_disconnect_ms(), None);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        atwinc.tick(10);
+        let health = atwinc.get_link_health();
+        assert_eq!(health.uptime_ms, Some(10));
+        assert_eq!(health.reconnect_count, 1);
+        assert_eq!(atwinc.get_reconnect_count(), 1);
+        assert_eq!(atwinc.get_last_disconnect_ms(), Some(1500));
+    }
+
+    #[test]
+    fn connection_info_bssid_and_channel() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.get_current_bssid(), None);
+        atwinc.request_connection_info().unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames.last().unwrap().op, REQ_GET_CONN_INFO);
+        let mut info = [0; 48];
+        info[..7].copy_from_slice(b"network");
+        info[33] = 2; // wpa psk
+        info[34..38].copy_from_slice(&[192, 168, 1, 20]);
+        info[38..44].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        info[44] = -60_i8 as u8;
+        info[45] = 11;
+        mock.push_frame(GID_WIFI, RESP_CONN_INFO, &info);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::ConnectionInfo));
+        let conn_info = atwinc.get_connection_info().unwrap();
+        assert_eq!(conn_info.ssid_bytes(), b"network");
+        assert_eq!(conn_info.ip, Ipv4Addr::new(192, 168, 1, 20));
+        assert_eq!(
+            atwinc.get_current_bssid(),
+            Some(MacAddress([1, 2, 3, 4, 5, 6]))
+        );
+        assert_eq!(atwinc.get_current_channel(), Some(11));
+        assert_eq!(atwinc.get_current_rssi(), Some(-60));
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[0, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.get_current_channel(), None);
+    }
+
+    /// Returns a dns response for host
+    fn dns_reply(host: &[u8], ip: [u8; 4]) -> [u8; 68] {
+        let mut data = [0; 68];
+        data[..host.len()].copy_from_slice(host);
+        data[64..].copy_from_slice(&ip);
+        data
+    }
+
+    #[test]
+    fn dns_cache() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let reply = dns_reply(b"example.com", [93, 184, 216, 34]);
+        mock.push_frame(GID_IP, SocketCommand::DnsResolve as u8, &reply);
+        let missing = dns_reply(b"missing.com", [0; 4]);
+        mock.push_frame(GID_IP, SocketCommand::DnsResolve as u8, &missing);
+        atwinc.handle_events().unwrap();
+        let ip = Ipv4Addr::new(93, 184, 216, 34);
+        assert_eq!(atwinc.get_cached_host(b"example.com"), Some(ip));
+        assert_eq!(atwinc.get_cached_host(b"missing.com"), None);
+        // Kept across a reset, dropped once the ttl passes
+        atwinc.recover().unwrap();
+        atwinc.tick(DEFAULT_DNS_CACHE_TTL_MS - 1);
+        assert_eq!(atwinc.get_cached_host(b"example.com"), Some(ip));
+        atwinc.tick(1);
+        assert_eq!(atwinc.get_cached_host(b"example.com"), None);
+        mock.push_frame(GID_IP, SocketCommand::DnsResolve as u8, &reply);
+        atwinc.handle_events().unwrap();
+        atwinc.clear_dns_cache();
+        assert_eq!(atwinc.get_cached_host(b"example.com"), None);
+        atwinc.set_dns_cache_ttl(0);
+        mock.push_frame(GID_IP, SocketCommand::DnsResolve as u8, &reply);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.get_cached_host(b"example.com"), None);
+    }
+
+    #[test]
+    fn dns_resolve() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        atwinc.request_dns_resolve("example.com").unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].op, SocketCommand::DnsResolve as u8);
+        assert_eq!(frames[0].data[..12], *b"example.com\0");
+        assert_eq!(
+            atwinc.request_dns_resolve("example.org"),
+            Err(Error::DnsInProgress)
+        );
+        assert_eq!(atwinc.get_resolved_address(), None);
+        let reply = dns_reply(b"example.com", [93, 184, 216, 34]);
+        mock.push_frame(GID_IP, SocketCommand::DnsResolve as u8, &reply);
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), Some(Event::DnsResolved));
+        assert_eq!(
+            atwinc.get_resolved_address(),
+            Some(Ipv4Addr::new(93, 184, 216, 34))
+        );
+        let long = "a".repeat(64);
+        assert_eq!(
+            atwinc.request_dns_resolve(&long),
+            Err(Error::HostnameTooLong)
+        );
+    }
+
+    #[test]
+    fn resolve_host() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let missing = dns_reply(b"missing.com", [0; 4]);
+        mock.push_reply(
+            SocketCommand::DnsResolve as u8,
+            GID_IP,
+            SocketCommand::DnsResolve as u8,
+            &missing,
+        );
+        assert_eq!(atwinc.resolve_host("missing.com"), Ok(None));
+        assert_eq!(atwinc.resolve_host("missing.com"), Err(Error::HostNotFound));
+        let reply = dns_reply(b"example.com", [93, 184, 216, 34]);
+        mock.push_reply(
+            SocketCommand::DnsResolve as u8,
+            GID_IP,
+            SocketCommand::DnsResolve as u8,
+            &reply,
+        );
+        assert_eq!(atwinc.resolve_host("example.com"), Ok(None));
+        let ip = Some(Ipv4Addr::new(93, 184, 216, 34));
+        assert_eq!(atwinc.resolve_host("example.com"), Ok(ip));
+        // Answered from the dns cache
+        mock.take_sent_frames();
+        assert_eq!(atwinc.resolve_host("example.com"), Ok(ip));
+        assert!(mock.take_sent_frames().is_empty());
+    }
+
+    #[test]
+    fn ping() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        atwinc
+            .request_ping(Ipv4Addr::new(192, 168, 1, 1), 3, 64)
+            .unwrap();
+        let frames = mock.take_sent_frames();
+        assert_eq!(frames[0].op, SocketCommand::Ping as u8);
+        assert_eq!(
+            frames[0].data[..12],
+            [192, 168, 1, 1, 0, 0, 0, 0, 3, 0, 64, 0]
+        );
+        assert!(atwinc.is_ping_in_progress());
+        assert_eq!(
+            atwinc.request_ping(Ipv4Addr::new(192, 168, 1, 1), 1, 64),
+            Err(Error::PingInProgress)
+        );
+        // round trip time 12ms, 3 replies, no failures
+        let mut reply = [0; 20];
+        reply[..4].copy_from_slice(&[192, 168, 1, 1]);
+        reply[8] = 12;
+        reply[12] = 3;
+        mock.push_frame(GID_IP, SocketCommand::Ping as u8, &reply);
+        atwinc.handle_events().unwrap();
+        assert!(!atwinc.is_ping_in_progress());
+        assert_eq!(atwinc.next_event(), Some(Event::PingDone));
+        assert_eq!(
+            atwinc.get_ping_result(),
+            Some(PingResult {
+                rtt_ms: 12,
+                error: PING_ERR_SUCCESS
+            })
+        );
+    }
+
+    #[test]
+    fn socket_handle_out_of_range() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let handle = MAX_SOCKETS as u8;
+        mock.push_frame(GID_IP, SocketCommand::Connect as u8, &[handle, 0, 0, 0]);
+        assert!(matches!(
+            atwinc.handle_events(),
+            Err(Error::HifError(HifError::InvalidFrame))
+        ));
+        assert_eq!(atwinc.next_event(), None);
+    }
+
+    #[test]
+    fn invalid_frame_finishes_reception() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        // Rejected after the reply is read, before its data
+        let reply = recv_reply(MAX_SOCKETS as u8, 1, 3, b"abc");
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &reply);
+        assert!(matches!(
+            atwinc.handle_events(),
+            Err(Error::HifError(HifError::InvalidFrame))
+        ));
+        assert!(!mock.has_pending_frames());
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+    }
+
+    #[test]
+    fn invalid_header_is_dropped() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        // unknown group id
+        mock.push_raw_frame(&[9, RESP_CON_STATE_CHANGED, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lengths shorter than the header
+        mock.push_raw_frame(&[GID_WIFI, RESP_CON_STATE_CHANGED, 2, 0, 0, 0, 0, 0]);
+        mock.push_raw_frame(&[GID_IP, SocketCommand::Recv as u8, 0, 0, 0, 0, 0, 0]);
+        // length longer than the frame
+        mock.push_raw_frame(&[GID_WIFI, RESP_CON_STATE_CHANGED, 0xff, 0xff, 0, 0, 0, 0]);
+        for _ in 0..4 {
+            assert!(matches!(
+                atwinc.handle_events(),
+                Err(Error::HifError(HifError::InvalidHeader))
+            ));
+        }
+        assert!(!mock.has_pending_frames());
+        mock.push_frame(GID_WIFI, RESP_CON_STATE_CHANGED, &[1, 0, 0, 0]);
+        atwinc.handle_events().unwrap();
+        assert!(matches!(atwinc.get_status(), Status::Connected));
+    }
+
+    #[test]
+    fn socket_events_carry_handle() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        // Sockets 0 to 4 have sessions 1 to 5
+        for _ in 0..5 {
+            atwinc.tcp_socket().unwrap();
+        }
+        // socket 2 connected
+        mock.push_frame(GID_IP, SocketCommand::Connect as u8, &[2, 0, 0, 0]);
+        // socket 3 received 5 bytes
+        let recv = recv_reply(3, 4, 5, b"hello");
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &recv);
+        // socket 3 timed out, socket 4 was closed
+        let recv = recv_reply(3, 4, -13, &[]);
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &recv);
+        let recv = recv_reply(4, 5, 0, &[]);
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &recv);
+        // socket 1 has a connection to accept
+        let mut accept = [0; 12];
+        accept[8] = 1;
+        accept[9] = 5;
+        mock.push_frame(GID_IP, SocketCommand::Accept as u8, &accept);
+        // socket 2 sent 10 bytes
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Send as u8,
+            &[2, 0, 10, 0, 3, 0, 0, 0],
+        );
+        atwinc.handle_events().unwrap();
+        assert_eq!(
+            atwinc.next_event(),
+            Some(Event::SocketWritable { handle: 2 })
+        );
+        assert_eq!(
+            atwinc.next_event(),
+            Some(Event::SocketReadable { handle: 3 })
+        );
+        assert_eq!(atwinc.next_event(), Some(Event::SocketClosed { handle: 4 }));
+        assert_eq!(
+            atwinc.next_event(),
+            Some(Event::SocketReadable { handle: 1 })
+        );
+        assert_eq!(
+            atwinc.next_event(),
+            Some(Event::SocketWritable { handle: 2 })
+        );
+        assert_eq!(atwinc.next_event(), None);
+    }
+
+    fn prng_reply(bytes: &[u8]) -> Vec<u8> {
+        let mut reply = vec![0, 0, 0, 0, bytes.len() as u8, 0, 0, 0];
+        reply.extend_from_slice(bytes);
+        reply
+    }
+
+    #[test]
+    fn test_fill_random() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let bytes: Vec<u8> = (0..8).collect();
+        mock.push_frame(GID_WIFI, RESP_GET_PRNG, &prng_reply(&bytes));
+        let mut buf = [0u8; 8];
+        atwinc.fill_random(&mut buf, 100).unwrap();
+        assert_eq!(&buf[..], &bytes[..]);
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].op, REQ_GET_PRNG);
+        assert_eq!(&sent[0].data[4..6], &[8, 0]);
+    }
+
+    #[test]
+    fn test_fill_random_chunks_requests() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let first: Vec<u8> = (0..32).collect();
+        mock.push_frame(GID_WIFI, RESP_GET_PRNG, &prng_reply(&first));
+        // Only the first chunk is answered
+        let mut buf = [0u8; 40];
+        assert_eq!(atwinc.fill_random(&mut buf, 10), Err(Error::RandomTimeout));
+        assert_eq!(&buf[..32], &first[..]);
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(&sent[0].data[4..6], &[32, 0]);
+        assert_eq!(&sent[1].data[4..6], &[8, 0]);
+    }
+
+    #[test]
+    fn test_fill_random_timeout() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(atwinc.fill_random(&mut buf, 10), Err(Error::RandomTimeout));
+    }
+
+    #[test]
+    fn test_tcp_socket_pool() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let mut sockets = Vec::new();
+        for handle in 0..TCP_SOCK_MAX {
+            let socket = atwinc.tcp_socket().unwrap();
+            assert_eq!(socket.handle() as usize, handle);
+            sockets.push(socket);
+        }
+        assert!(matches!(atwinc.tcp_socket(), Err(Error::NoFreeSockets)));
+        // Opening a socket sends nothing
+        assert!(mock.take_sent_frames().is_empty());
+        let socket = sockets.remove(3);
+        atwinc.close_socket(socket).unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].gid, GID_IP);
+        assert_eq!(sent[0].op, SocketCommand::Close as u8);
+        // socket 3 was the fourth session
+        assert_eq!(sent[0].data, vec![3, 0, 4, 0]);
+        assert_eq!(atwinc.tcp_socket().unwrap().handle(), 3);
+    }
+
+    #[test]
+    fn test_poll_sockets() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let first = atwinc.tcp_socket().unwrap();
+        let second = atwinc.tcp_socket().unwrap();
+        let third = atwinc.tcp_socket().unwrap();
+        let readiness = atwinc.poll_sockets(&[&first, &second]).unwrap();
+        assert!(readiness.is_empty());
+        // first connected, second received 4 bytes, third failed
+        mock.push_frame(GID_IP, SocketCommand::Connect as u8, &[0, 0, 0, 0]);
+        let recv = recv_reply(1, 2, 4, b"data");
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &recv);
+        mock.push_frame(GID_IP, SocketCommand::Connect as u8, &[2, 0xf2, 0, 0]);
+        let readiness = atwinc.poll_sockets(&[&first, &second]).unwrap();
+        assert!(readiness.is_writable(&first));
+        assert!(!readiness.is_readable(&first));
+        assert!(readiness.is_readable(&second));
+        // Only the sockets passed in are reported
+        assert!(!readiness.is_closed(&third));
+        let readiness = atwinc.poll_sockets(&[&third]).unwrap();
+        assert!(readiness.is_closed(&third));
+        // A freed handle doesn't keep its readiness
+        atwinc.close_socket(first).unwrap();
+        let first = atwinc.tcp_socket().unwrap();
+        assert!(atwinc.poll_sockets(&[&first]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_socket_keepalive() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        mock.take_sent_frames();
+        // 30s idle, 5.2s rounds up to 11 units
+        atwinc.set_socket_keepalive(&socket, 30_000, 5_200).unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 3);
+        assert!(sent
+            .iter()
+            .all(|f| f.gid == GID_IP && f.op == SocketCommand::SetSocketOption as u8));
+        assert_eq!(sent[0].data, vec![0, 5, 1, 0, 60, 0, 0, 0]);
+        assert_eq!(sent[1].data, vec![0, 6, 1, 0, 11, 0, 0, 0]);
+        assert_eq!(sent[2].data, vec![0, 4, 1, 0, 1, 0, 0, 0]);
+        assert_eq!(
+            atwinc.set_socket_keepalive(&socket, 30_000, MAX_KEEPALIVE_INTERVAL_MS + 1),
+            Err(Error::InvalidSocketOption)
+        );
+        atwinc.set_socket_keepalive(&socket, 0, 0).unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].data, vec![0, 4, 1, 0, 0, 0, 0, 0]);
+        // recover frees every socket
+        atwinc.recover().unwrap();
+        assert_eq!(
+            atwinc.set_socket_keepalive(&socket, 30_000, 5_000),
+            Err(Error::SocketNotOpen)
+        );
+    }
+
+    /// Builds a receive reply with the data
+    /// straight after the reply header
+    fn recv_reply(handle: u8, session: u16, status: i16, bytes: &[u8]) -> Vec<u8> {
+        let mut reply = vec![0u8; 16];
+        reply[8..10].copy_from_slice(&status.to_le_bytes());
+        reply[10..12].copy_from_slice(&16u16.to_le_bytes());
+        reply[12] = handle;
+        reply[14..16].copy_from_slice(&session.to_le_bytes());
+        reply.extend_from_slice(bytes);
+        reply
+    }
+
+    #[test]
+    fn test_tcp_send() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        mock.take_sent_frames();
+        assert_eq!(atwinc.tcp_send(&socket, b"hello").unwrap(), Some(5));
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].gid, GID_IP);
+        assert_eq!(sent[0].op, SocketCommand::Send as u8);
+        // socket, size, session then the payload at offset 80
+        assert_eq!(sent[0].data.len(), 85);
+        assert_eq!(&sent[0].data[..4], &[0, 0, 5, 0]);
+        assert_eq!(&sent[0].data[12..14], &[1, 0]);
+        assert_eq!(&sent[0].data[80..], b"hello");
+        // Long sends are split
+        let long = [0x55u8; 2000];
+        assert_eq!(
+            atwinc.tcp_send(&socket, &long).unwrap(),
+            Some(SOCKET_BUFFER_MAX_LENGTH)
+        );
+    }
+
+    #[test]
+    fn test_tcp_receive() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        mock.take_sent_frames();
+        let mut buf = [0u8; 3];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].op, SocketCommand::Recv as u8);
+        // wait forever, socket 0, session 1, 1400 bytes free
+        assert_eq!(
+            sent[0].data,
+            vec![0xff, 0xff, 0xff, 0xff, 0, 0, 1, 0, 0x78, 0x05, 0, 0]
+        );
+        // Only one request is outstanding
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        assert!(mock.take_sent_frames().is_empty());
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, 5, b"abcde"),
+        );
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), Some(3));
+        assert_eq!(&buf, b"abc");
+        assert!(atwinc
+            .poll_sockets(&[&socket])
+            .unwrap()
+            .is_readable(&socket));
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), Some(2));
+        assert_eq!(&buf[..2], b"de");
+        assert!(!atwinc
+            .poll_sockets(&[&socket])
+            .unwrap()
+            .is_readable(&socket));
+        // The peer closes the connection
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        mock.push_frame(GID_IP, SocketCommand::Recv as u8, &recv_reply(0, 1, 0, &[]));
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), Some(0));
+        assert_eq!(atwinc.tcp_send(&socket, b"x"), Err(Error::SocketClosed));
+    }
+
+    #[test]
+    fn test_tcp_receive_retries_failed_request() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        mock.take_sent_frames();
+        let mut buf = [0u8; 4];
+        mock.fail_allocations(1);
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        assert!(mock.take_sent_frames().is_empty());
+        // The request is sent again and its reply read
+        mock.push_reply(
+            SocketCommand::Recv as u8,
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, 4, b"data"),
+        );
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        assert_eq!(mock.take_sent_frames().len(), 1);
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), Some(4));
+        assert_eq!(&buf, b"data");
+    }
+
+    #[test]
+    fn test_bytes_available() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        assert_eq!(atwinc.bytes_available(&socket), 0);
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, 5, b"abcde"),
+        );
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.bytes_available(&socket), 5);
+        let mut buf = [0u8; 3];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), Some(3));
+        assert_eq!(atwinc.bytes_available(&socket), 2);
+        atwinc.close_socket(socket).unwrap();
+    }
+
+    #[test]
+    fn test_stale_reply_ignored_after_handle_reuse() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let old = atwinc.tcp_socket().unwrap();
+        atwinc.close_socket(old).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        assert_eq!(socket.handle(), 0);
+        // Late replies for the closed socket with session 1
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, -12, &[]),
+        );
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, 3, b"old"),
+        );
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Send as u8,
+            &[0, 0, 0xf4, 0xff, 1, 0, 0, 0],
+        );
+        atwinc.handle_events().unwrap();
+        assert_eq!(atwinc.next_event(), None);
+        let readiness = atwinc.poll_sockets(&[&socket]).unwrap();
+        assert!(readiness.is_empty());
+        assert_eq!(atwinc.tcp_send(&socket, b"hi").unwrap(), Some(2));
+        let mut buf = [0u8; 4];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tcp_receive_overflow() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        let mut buf = [0u8; SOCKET_RX_BUFFER_SIZE];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        let bytes = vec![1u8; SOCKET_RX_BUFFER_SIZE + 10];
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, bytes.len() as i16, &bytes),
+        );
+        // The bytes that fit are read before the overflow
+        assert_eq!(
+            atwinc.tcp_receive(&socket, &mut buf).unwrap(),
+            Some(SOCKET_RX_BUFFER_SIZE)
+        );
+        assert!(!mock.has_pending_frames());
+        assert_eq!(
+            atwinc.tcp_receive(&socket, &mut buf),
+            Err(Error::SocketBufferOverflow)
+        );
+        // It is only reported once
+        mock.take_sent_frames();
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        assert_eq!(mock.take_sent_frames().len(), 1);
+    }
+
+    #[test]
+    fn test_tcp_receive_full_segment() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let socket = atwinc.tcp_socket().unwrap();
+        let mut buf = [0u8; SOCKET_BUFFER_MAX_LENGTH];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        let bytes: Vec<u8> = (0..SOCKET_BUFFER_MAX_LENGTH).map(|i| i as u8).collect();
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Recv as u8,
+            &recv_reply(0, 1, bytes.len() as i16, &bytes),
+        );
+        assert_eq!(
+            atwinc.tcp_receive(&socket, &mut buf).unwrap(),
+            Some(SOCKET_BUFFER_MAX_LENGTH)
+        );
+        assert_eq!(buf[..], bytes[..]);
+    }
+
+    #[test]
+    fn test_connect_tls() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let mut socket = atwinc.tcp_socket().unwrap();
+        mock.take_sent_frames();
+        let addr = SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 443);
+        assert_eq!(
+            atwinc.connect_tls(&mut socket, addr, "example.com"),
+            Ok(None)
+        );
+        assert!(socket.is_tls());
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].op, SocketCommand::SslCreate as u8);
+        assert_eq!(sent[0].data[..4], [0, 0, 0, 0]);
+        // socket, sni option, session, length with the null terminator
+        assert_eq!(sent[1].op, SocketCommand::SslSetSockOpt as u8);
+        assert_eq!(sent[1].data[..8], [0, 2, 1, 0, 12, 0, 0, 0]);
+        assert_eq!(&sent[1].data[8..20], b"example.com\0");
+        assert_eq!(sent[2].op, SocketCommand::SslConnect as u8);
+        assert_eq!(
+            sent[2].data[..8],
+            sockaddr_to_bytes(*addr.ip(), addr.port())
+        );
+        assert_eq!(sent[2].data[8..12], [0, 0x21, 1, 0]);
+        // Still handshaking
+        assert_eq!(
+            atwinc.connect_tls(&mut socket, addr, "example.com"),
+            Ok(None)
+        );
+        assert!(mock.take_sent_frames().is_empty());
+        // Connected with the payload at offset 93 from the hif header
+        mock.push_frame(GID_IP, SocketCommand::SslConnect as u8, &[0, 0, 93, 0]);
+        assert_eq!(
+            atwinc.connect_tls(&mut socket, addr, "example.com"),
+            Ok(Some(()))
+        );
+        assert_eq!(atwinc.tcp_send(&socket, b"hi").unwrap(), Some(2));
+        let mut buf = [0u8; 8];
+        assert_eq!(atwinc.tcp_receive(&socket, &mut buf).unwrap(), None);
+        atwinc.close_socket(socket).unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent[0].op, SocketCommand::SslSend as u8);
+        assert_eq!(&sent[0].data[85..], b"hi");
+        assert_eq!(sent[1].op, SocketCommand::SslRecv as u8);
+        assert_eq!(sent[2].op, SocketCommand::SslClose as u8);
+    }
+
+    #[test]
+    fn test_set_cipher_suites() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        atwinc
+            .set_cipher_suites(&[
+                CipherSuite::EcdheRsaWithAes128GcmSha256,
+                CipherSuite::EcdheEcdsaWithAes128GcmSha256,
+                CipherSuite::RsaWithAes128CbcSha,
+            ])
+            .unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent[0].gid, GID_IP);
+        assert_eq!(sent[0].op, SocketCommand::SslSetCsList as u8);
+        assert_eq!(sent[0].data[..4], [0x01, 0xc0, 0, 0]);
+        assert_eq!(
+            atwinc.set_cipher_suites(&[]),
+            Err(Error::InvalidSocketOption)
+        );
+        assert!(mock.take_sent_frames().is_empty());
+    }
+
+    #[test]
+    fn test_connect_tls_handshake_failed() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let mut socket = atwinc.tcp_socket().unwrap();
+        let addr = SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 443);
+        atwinc
+            .connect_tls(&mut socket, addr, "example.com")
+            .unwrap();
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::SslConnect as u8,
+            &[0, -12i8 as u8, 0, 0],
+        );
+        assert_eq!(
+            atwinc.connect_tls(&mut socket, addr, "example.com"),
+            Err(Error::TlsHandshakeFailed)
+        );
+        assert_eq!(
+            atwinc.tcp_send(&socket, b"hi"),
+            Err(Error::TlsHandshakeFailed)
+        );
+        let long = "a".repeat(64);
+        let mut other = atwinc.tcp_socket().unwrap();
+        assert_eq!(
+            atwinc.connect_tls(&mut other, addr, &long),
+            Err(Error::HostnameTooLong)
+        );
+    }
+
+    #[test]
+    fn test_tcp_accept() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        let listener = atwinc.tcp_socket().unwrap();
+        atwinc.tcp_bind(&listener, 8080).unwrap();
+        atwinc.tcp_listen(&listener).unwrap();
+        let sent = mock.take_sent_frames();
+        assert_eq!(sent[sent.len() - 2].op, SocketCommand::Bind as u8);
+        // port 8080 on any address, socket 0, session 1
+        assert_eq!(
+            sent[sent.len() - 2].data,
+            vec![2, 0, 0x1f, 0x90, 0, 0, 0, 0, 0, 0, 1, 0]
+        );
+        assert_eq!(sent[sent.len() - 1].data, vec![0, 1, 1, 0]);
+        assert!(atwinc.tcp_accept(&listener).unwrap().is_none());
+        // 192.168.1.9:12345 connected as socket 4
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Accept as u8,
+            &[2, 0, 0x30, 0x39, 192, 168, 1, 9, 0, 4, 0, 0],
+        );
+        let (client, peer) = atwinc.tcp_accept(&listener).unwrap().unwrap();
+        assert_eq!(client.handle(), 4);
+        assert_eq!(
+            peer,
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 9), 12345)
+        );
+        assert!(atwinc.tcp_accept(&listener).unwrap().is_none());
+        // A rejected listen closes the socket
+        mock.push_frame(GID_IP, SocketCommand::Listen as u8, &[0, 0xfa, 1, 0]);
+        assert!(matches!(
+            atwinc.tcp_accept(&listener),
+            Err(Error::SocketClosed)
+        ));
+    }
+
+    #[test]
+    fn test_echo_server_sequential_clients() {
+        let mock = MockAtwinc::new();
+        let mut atwinc = mock.driver(false).unwrap();
+        mock.take_sent_frames();
+        let recv = SocketCommand::Recv as u8;
+        mock.push_reply(
+            SocketCommand::Bind as u8,
+            GID_IP,
+            SocketCommand::Bind as u8,
+            &[0, 0, 1, 0],
+        );
+        mock.push_reply(
+            SocketCommand::Listen as u8,
+            GID_IP,
+            SocketCommand::Listen as u8,
+            &[0, 0, 1, 0],
+        );
+        mock.push_frame(
+            GID_IP,
+            SocketCommand::Accept as u8,
+            &[2, 0, 0x30, 0x39, 10, 0, 0, 2, 0, 1, 0, 0],
+        );
+        mock.push_reply(recv, GID_IP, recv, &recv_reply(1, 1, 5, b"hello"));
+        mock.push_reply(recv, GID_IP, recv, &recv_reply(1, 1, 0, &[]));
+        // The second client connects once the first is closed
+        mock.push_reply(
+            SocketCommand::Close as u8,
+            GID_IP,
+            SocketCommand::Accept as u8,
+            &[2, 0, 0x30, 0x3a, 10, 0, 0, 3, 0, 1, 0, 0],
+        );
+        mock.push_reply(recv, GID_IP, recv, &recv_reply(1, 1, 3, b"abc"));
+        mock.push_reply(recv, GID_IP, recv, &recv_reply(1, 1, 0, &[]));
+        atwinc.run_echo_server(7, Some(2)).unwrap();
+        let frames = mock.take_sent_frames();
+        let sent: Vec<(u8, u8)> = frames
+            .iter()
+            .filter(|f| f.op != recv)
+            .map(|f| (f.op, f.data[0]))
+            .collect();
+        let bind = SocketCommand::Bind as u8;
+        let listen = SocketCommand::Listen as u8;
+        let send = SocketCommand::Send as u8;
+        let close = SocketCommand::Close as u8;
+        let echoed: Vec<&[u8]> = frames
+            .iter()
+            .filter(|f| f.op == send)
+            .map(|f| &f.data[80..])
+            .collect();
+        assert_eq!(echoed, vec![&b"hello"[..], &b"abc"[..]]);
+        assert_eq!(
+            sent,
+            vec![
+                (bind, 2),
+                (listen, 0),
+                (send, 1),
+                (close, 1),
+                (send, 1),
+                (close, 1),
+                (close, 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod socket_unit_tests {
+    use atwinc1500::error::Error;
+    use atwinc1500::socket::{ipv4_to_bytes, sockaddr_to_bytes, u16_to_be_bytes, SocketCommand};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn port_is_network_order() {
+        assert_eq!(u16_to_be_bytes(80), [0x00, 0x50]);
+        assert_eq!(u16_to_be_bytes(0x1f90), [0x1f, 0x90]);
+    }
+
+    #[test]
+    fn ipv4_is_network_order() {
+        assert_eq!(
+            ipv4_to_bytes(Ipv4Addr::new(192, 168, 1, 42)),
+            [192, 168, 1, 42]
+        );
+    }
+
+    #[test]
+    fn sockaddr_layout() {
+        assert_eq!(
+            sockaddr_to_bytes(Ipv4Addr::new(10, 0, 0, 1), 443),
+            [2, 0, 0x01, 0xbb, 10, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn socket_command_direction() {
+        assert!(SocketCommand::Connect.is_request());
+        assert!(SocketCommand::Connect.is_response());
+        assert!(!SocketCommand::Accept.is_request());
+        assert!(SocketCommand::Accept.is_response());
+        assert!(SocketCommand::Close.is_request());
+        assert!(!SocketCommand::Close.is_response());
+    }
+
+    #[test]
+    fn socket_command_try_from() {
+        assert_eq!(SocketCommand::try_from(68), Ok(SocketCommand::Connect));
+        assert_eq!(SocketCommand::try_from(85), Ok(SocketCommand::SslExpCheck));
+        assert_eq!(
+            SocketCommand::try_from(64),
+            Err(Error::InvalidSocketCommand)
+        );
+    }
+}
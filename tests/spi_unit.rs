@@ -1,8 +1,9 @@
 #[cfg(test)]
 mod spi_unit_tests {
-    use atwinc1500::error::Error;
+    use atwinc1500::error::{Error, SpiErrorContext};
     use atwinc1500::registers;
     use atwinc1500::spi;
+    use atwinc1500::types::InitConfig;
     use embedded_hal_mock::pin::{
         Mock as PinMock, State as PinState, Transaction as PinTransaction,
     };
@@ -64,6 +65,7 @@ mod spi_unit_tests {
                     0x0,
                     0x0,
                     0x0,
+                    0x0,
                 ],
                 // Receive
                 vec![
@@ -78,6 +80,7 @@ mod spi_unit_tests {
                     ((FINISH_BOOT_VAL >> 8) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 16) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 24) & 0xff) as u8,
+                    0x0, // padding
                 ],
             ),
         ];
@@ -87,12 +90,10 @@ mod spi_unit_tests {
             PinTransaction::set(PinState::High),
         ];
         let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         match spi_bus.read_register(registers::BOOTROM_REG) {
             Ok(v) => assert_eq!(v, FINISH_BOOT_VAL),
-            Err(_) => assert!(false),
+            Err(e) => panic!("{}", e),
         }
     }
 
@@ -118,6 +119,7 @@ mod spi_unit_tests {
                     0x0,
                     0x0,
                     0x0,
+                    0x0,
                 ],
                 vec![
                     0x0,
@@ -131,6 +133,7 @@ mod spi_unit_tests {
                     ((FINISH_BOOT_VAL >> 8) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 16) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 24) & 0xff) as u8,
+                    0x0, // padding
                 ],
             ),
         ];
@@ -140,13 +143,19 @@ mod spi_unit_tests {
             PinTransaction::set(PinState::High),
         ];
         let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         match spi_bus.read_register(registers::BOOTROM_REG) {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(e) => assert_eq!(e, Error::SpiReadRegisterError),
         }
+        assert_eq!(
+            spi_bus.last_error(),
+            Some(SpiErrorContext {
+                command: spi::commands::CMD_SINGLE_READ,
+                address: registers::BOOTROM_REG,
+                error: Error::SpiReadRegisterError,
+            })
+        );
     }
 
     #[test]
@@ -169,6 +178,7 @@ mod spi_unit_tests {
                     0x0,
                     0x0,
                     0x0,
+                    0x0,
                 ],
                 // Receive
                 vec![
@@ -184,6 +194,7 @@ mod spi_unit_tests {
                     ((FINISH_BOOT_VAL >> 8) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 16) & 0xff) as u8,
                     ((FINISH_BOOT_VAL >> 24) & 0xff) as u8,
+                    0x0, // padding
                 ],
             ),
         ];
@@ -195,12 +206,10 @@ mod spi_unit_tests {
         let spi = SpiMock::new(&spi_expect);
         let cs = PinMock::new(&pin_expect);
         let mut spi_bus = spi::SpiBus::new(spi, cs, true);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         match spi_bus.read_register(registers::BOOTROM_REG) {
             Ok(v) => assert_eq!(v, FINISH_BOOT_VAL),
-            Err(_) => assert!(false),
+            Err(e) => panic!("{}", e),
         }
     }
 
@@ -240,9 +249,7 @@ mod spi_unit_tests {
             PinTransaction::set(PinState::High),
         ];
         let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         assert!(spi_bus
             .write_register(registers::BOOTROM_REG, START_FIRMWARE)
             .is_ok());
@@ -284,13 +291,19 @@ mod spi_unit_tests {
             PinTransaction::set(PinState::High),
         ];
         let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         match spi_bus.write_register(registers::BOOTROM_REG, START_FIRMWARE) {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(e) => assert_eq!(e, Error::SpiWriteRegisterError),
         }
+        assert_eq!(
+            spi_bus.last_error(),
+            Some(SpiErrorContext {
+                command: spi::commands::CMD_SINGLE_WRITE,
+                address: registers::BOOTROM_REG,
+                error: Error::SpiWriteRegisterError,
+            })
+        );
     }
 
     #[test]
@@ -333,11 +346,361 @@ mod spi_unit_tests {
         let spi = SpiMock::new(&spi_expect);
         let cs = PinMock::new(&pin_expect);
         let mut spi_bus = spi::SpiBus::new(spi, cs, true);
-        if spi_bus.init_cs().is_err() {
-            assert!(false);
-        }
+        assert!(spi_bus.init_cs().is_ok());
         assert!(spi_bus
             .write_register(registers::BOOTROM_REG, START_FIRMWARE)
             .is_ok());
     }
+
+    /// Returns the transactions for a 4 byte
+    /// data write followed by the given
+    /// responses to the final poll
+    fn write_data_expect(address: u32, acks: &[u8]) -> (Vec<SpiTransaction>, Vec<PinTransaction>) {
+        let mut spi_expect = vec![
+            SpiTransaction::transfer(
+                vec![
+                    spi::commands::CMD_DMA_EXT_WRITE,
+                    (address >> 16) as u8,
+                    (address >> 8) as u8,
+                    address as u8,
+                    0x0,
+                    0x0,
+                    0x4,
+                ],
+                vec![0x0; 7],
+            ),
+            SpiTransaction::transfer(vec![0x0, 0x0], vec![spi::commands::CMD_DMA_EXT_WRITE, 0x0]),
+            SpiTransaction::transfer(vec![0xf3], vec![0x0]),
+            SpiTransaction::transfer(vec![1, 2, 3, 4], vec![0x0; 4]),
+        ];
+        let mut pin_expect = vec![PinTransaction::set(PinState::High)];
+        for _ in 0..4 {
+            pin_expect.push(PinTransaction::set(PinState::Low));
+            pin_expect.push(PinTransaction::set(PinState::High));
+        }
+        for ack in acks {
+            spi_expect.push(SpiTransaction::transfer(vec![0x0], vec![*ack]));
+            pin_expect.push(PinTransaction::set(PinState::Low));
+            pin_expect.push(PinTransaction::set(PinState::High));
+        }
+        (spi_expect, pin_expect)
+    }
+
+    /// Adds the transactions for the terminate
+    /// command sent after a failed data transfer
+    fn push_terminate(spi_expect: &mut Vec<SpiTransaction>, pin_expect: &mut Vec<PinTransaction>) {
+        let cmd = spi::commands::CMD_TERMINATE;
+        spi_expect.push(SpiTransaction::transfer(
+            vec![cmd, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0],
+            vec![0x0, 0x0, 0x0, 0x0, 0x0, cmd, 0x0, 0x0],
+        ));
+        pin_expect.push(PinTransaction::set(PinState::Low));
+        pin_expect.push(PinTransaction::set(PinState::High));
+    }
+
+    #[test]
+    fn terminate_transaction() {
+        let cmd = spi::commands::CMD_TERMINATE;
+        let spi_expect = [
+            SpiTransaction::transfer(
+                vec![cmd, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0],
+                vec![0x0, 0x0, 0x0, 0x0, 0x0, 0x0, cmd, 0x0],
+            ),
+            SpiTransaction::transfer(
+                vec![cmd, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0],
+                vec![0x0, 0x0, 0x0, 0x0, 0x0, cmd, 0x3, 0x0],
+            ),
+        ];
+        let mut pin_expect = vec![PinTransaction::set(PinState::High)];
+        for _ in 0..2 {
+            pin_expect.push(PinTransaction::set(PinState::Low));
+            pin_expect.push(PinTransaction::set(PinState::High));
+        }
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        // A late response is accepted
+        assert!(spi_bus.terminate_transaction().is_ok());
+        assert_eq!(
+            spi_bus.terminate_transaction(),
+            Err(Error::SpiTerminateError)
+        );
+    }
+
+    #[test]
+    fn write_data_busy_then_ack() {
+        let (spi_expect, pin_expect) = write_data_expect(0x40000, &[0x0, 0xff, 0xc3]);
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert!(spi_bus.write_data(&mut [1, 2, 3, 4], 0x40000, 4).is_ok());
+    }
+
+    #[test]
+    fn write_data_nak() {
+        let (mut spi_expect, mut pin_expect) = write_data_expect(0x40000, &[0x0, 0x5a]);
+        push_terminate(&mut spi_expect, &mut pin_expect);
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.write_data(&mut [1, 2, 3, 4], 0x40000, 4),
+            Err(Error::SpiWriteDataError)
+        );
+    }
+
+    #[test]
+    fn write_data_no_ack() {
+        let (mut spi_expect, mut pin_expect) = write_data_expect(0x40000, &[0x0; 10]);
+        push_terminate(&mut spi_expect, &mut pin_expect);
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.write_data(&mut [1, 2, 3, 4], 0x40000, 4),
+            Err(Error::SpiWriteDataError)
+        );
+    }
+
+    /// Returns the transactions for a 4 byte
+    /// data read that polls the given number
+    /// of times without the Atwinc1500 responding,
+    /// then terminates the read
+    fn read_data_timeout_expect(
+        address: u32,
+        polls: usize,
+    ) -> (Vec<SpiTransaction>, Vec<PinTransaction>) {
+        let mut spi_expect = vec![SpiTransaction::transfer(
+            vec![
+                spi::commands::CMD_DMA_EXT_READ,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+                0x0,
+                0x0,
+                0x4,
+            ],
+            vec![0x0; 7],
+        )];
+        let mut pin_expect = vec![
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        for _ in 0..polls {
+            spi_expect.push(SpiTransaction::transfer(vec![0x0; 3], vec![0x0; 3]));
+            pin_expect.push(PinTransaction::set(PinState::Low));
+            pin_expect.push(PinTransaction::set(PinState::High));
+        }
+        push_terminate(&mut spi_expect, &mut pin_expect);
+        (spi_expect, pin_expect)
+    }
+
+    #[test]
+    fn read_data_timeout() {
+        let (spi_expect, pin_expect) =
+            read_data_timeout_expect(0x50000, InitConfig::DEFAULT_READ_RETRIES as usize);
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.read_data(&mut [0; 4], 0x50000, 4),
+            Err(Error::SpiReadDataTimeout)
+        );
+    }
+
+    #[test]
+    fn read_data_retries() {
+        let (spi_expect, pin_expect) = read_data_timeout_expect(0x50000, 25);
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        spi_bus.set_read_retries(25);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.read_data(&mut [0; 4], 0x50000, 4),
+            Err(Error::SpiReadDataTimeout)
+        );
+    }
+
+    #[test]
+    fn write_data_split_transfers() {
+        let address: u32 = 0x40000;
+        let spi_expect = [
+            SpiTransaction::transfer(
+                vec![
+                    spi::commands::CMD_DMA_EXT_WRITE,
+                    (address >> 16) as u8,
+                    (address >> 8) as u8,
+                    address as u8,
+                    0x0,
+                    0x0,
+                ],
+                vec![0x0; 6],
+            ),
+            // The command is split too
+            SpiTransaction::transfer(vec![0x7], vec![0x0]),
+            SpiTransaction::transfer(vec![0x0, 0x0], vec![spi::commands::CMD_DMA_EXT_WRITE, 0x0]),
+            SpiTransaction::transfer(vec![0xf3], vec![0x0]),
+            SpiTransaction::transfer(vec![1, 2, 3, 4, 5, 6], vec![0x0; 6]),
+            SpiTransaction::transfer(vec![7], vec![0x0]),
+            SpiTransaction::transfer(vec![0x0], vec![0xc3]),
+        ];
+        let mut pin_expect = vec![PinTransaction::set(PinState::High)];
+        for _ in 0..5 {
+            pin_expect.push(PinTransaction::set(PinState::Low));
+            pin_expect.push(PinTransaction::set(PinState::High));
+        }
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        spi_bus.set_max_transfer(6);
+        assert!(spi_bus.init_cs().is_ok());
+        assert!(spi_bus
+            .write_data(&mut [1, 2, 3, 4, 5, 6, 7], address, 7)
+            .is_ok());
+    }
+
+    #[test]
+    fn read_register_late_response() {
+        // The response arrives one byte late
+        // after an extra byte of zero padding
+        const FINISH_BOOT_VAL: u32 = 0x10add09e;
+        let address: u32 = registers::BOOTROM_REG;
+        let spi_expect = [SpiTransaction::transfer(
+            vec![
+                spi::commands::CMD_SINGLE_READ,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+            ],
+            vec![
+                0x0,
+                0x0,
+                0x0,
+                0x0,
+                0x0, // padding
+                spi::commands::CMD_SINGLE_READ,
+                0x0,
+                0xf3,
+                (FINISH_BOOT_VAL & 0xff) as u8,
+                ((FINISH_BOOT_VAL >> 8) & 0xff) as u8,
+                ((FINISH_BOOT_VAL >> 16) & 0xff) as u8,
+                ((FINISH_BOOT_VAL >> 24) & 0xff) as u8,
+            ],
+        )];
+        let pin_expect = [
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.read_register(registers::BOOTROM_REG),
+            Ok(FINISH_BOOT_VAL)
+        );
+    }
+
+    /// Sends a data command with crc enabled and
+    /// checks the crc follows the command bytes
+    fn assert_command_crc(cmd: u8, command_size: usize, crc: u8) {
+        let address: u32 = 0x1000;
+        let mut expected = vec![
+            cmd,
+            (address >> 16) as u8,
+            (address >> 8) as u8,
+            address as u8,
+        ];
+        expected.resize(command_size, 0x0);
+        expected[command_size - 1] = 0x4;
+        expected.push(crc);
+        let spi_expect = [SpiTransaction::transfer(
+            expected,
+            vec![0x0; command_size + 1],
+        )];
+        let pin_expect = [
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        let spi = SpiMock::new(&spi_expect);
+        let cs = PinMock::new(&pin_expect);
+        let mut spi_bus = spi::SpiBus::new(spi, cs, true);
+        assert!(spi_bus.init_cs().is_ok());
+        let mut cmd_buffer = vec![0x0; command_size + 1];
+        assert!(spi_bus
+            .command(&mut cmd_buffer, cmd, address, 0, 4, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn dma_ext_write_crc() {
+        assert_command_crc(spi::commands::CMD_DMA_EXT_WRITE, 7, 0x02);
+    }
+
+    #[test]
+    fn dma_ext_read_crc() {
+        assert_command_crc(spi::commands::CMD_DMA_EXT_READ, 7, 0xd8);
+    }
+
+    #[test]
+    fn dma_write_crc() {
+        assert_command_crc(spi::commands::CMD_DMA_WRITE, 6, 0xa2);
+    }
+
+    #[test]
+    fn read_register_no_response() {
+        let address: u32 = registers::BOOTROM_REG;
+        let mut command = vec![
+            spi::commands::CMD_SINGLE_READ,
+            (address >> 16) as u8,
+            (address >> 8) as u8,
+            address as u8,
+        ];
+        command.resize(12, 0x0);
+        // A floating MISO line reads as 0xff
+        let spi_expect = [SpiTransaction::transfer(command, vec![0xff; 12])];
+        let pin_expect = [
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.read_register(registers::BOOTROM_REG),
+            Err(Error::SpiNoResponse)
+        );
+    }
+
+    #[test]
+    fn write_register_no_response() {
+        let address: u32 = registers::BOOTROM_REG;
+        let spi_expect = [SpiTransaction::transfer(
+            vec![
+                spi::commands::CMD_SINGLE_WRITE,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+                0x0,
+                0x0,
+                0x0,
+                0x1,
+                0x0,
+                0x0,
+            ],
+            vec![0x0; 10],
+        )];
+        let pin_expect = [
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        let mut spi_bus = get_fixture(&spi_expect, &pin_expect);
+        assert!(spi_bus.init_cs().is_ok());
+        assert_eq!(
+            spi_bus.write_register(registers::BOOTROM_REG, 1),
+            Err(Error::SpiNoResponse)
+        );
+    }
 }
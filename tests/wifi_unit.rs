@@ -0,0 +1,296 @@
+#[cfg(test)]
+mod wifi_unit_tests {
+    use atwinc1500::error::{Error, HifError};
+    use atwinc1500::types::{IpConfig, MacAddress};
+    use atwinc1500::wifi::{
+        rssi_to_quality, Channel, ConnectionParameters, OldConnection, SaveCredentials, ScanRegion,
+        ScanResult, SecurityType, StateChange, StateChangeErrorCode, CONNECTION_BYTES_VERSION,
+    };
+    use std::net::Ipv4Addr;
+
+    /// Returns a scan result response
+    /// as sent by the Atwinc1500
+    fn scan_result_bytes(channel: u8) -> [u8; 44] {
+        let mut data: [u8; 44] = [0; 44];
+        data[0] = 2; // index
+        data[1] = (-67_i8) as u8; // rssi
+        data[2] = 2; // auth type
+        data[3] = channel;
+        data[4..10].copy_from_slice(&[0xf8, 0xf0, 0x05, 0x01, 0x02, 0x03]);
+        data[10..17].copy_from_slice(b"network");
+        data
+    }
+
+    #[test]
+    fn scan_result_from_bytes() {
+        let result = ScanResult::from(scan_result_bytes(6));
+        assert_eq!(result.index, 2);
+        assert_eq!(result.rssi, -67);
+        assert_eq!(result.auth_type, 2);
+        assert_eq!(result.channel, 6);
+        assert_eq!(result.bssid.0, [0xf8, 0xf0, 0x05, 0x01, 0x02, 0x03]);
+        assert_eq!(&result.ssid[0..7], b"network");
+        assert!(result.ssid[7..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn scan_result_channel_enum() {
+        let result = ScanResult::from(scan_result_bytes(11));
+        assert!(matches!(result.channel_enum(), Channel::Ch11));
+    }
+
+    #[test]
+    fn scan_result_channel_enum_out_of_range() {
+        let result = ScanResult::from(scan_result_bytes(42));
+        assert!(matches!(result.channel_enum(), Channel::Any));
+    }
+
+    #[test]
+    fn channel_try_from() {
+        assert!(matches!(Channel::try_from(1), Ok(Channel::Ch1)));
+        assert!(matches!(Channel::try_from(255), Ok(Channel::Any)));
+        assert!(matches!(Channel::try_from(0), Err(Error::InvalidChannel)));
+        assert!(matches!(Channel::try_from(17), Err(Error::InvalidChannel)));
+    }
+
+    #[test]
+    fn ip_config_from_bytes() {
+        let data: [u8; 20] = [
+            192, 168, 1, 42, // ip
+            192, 168, 1, 1, // gateway
+            8, 8, 8, 8, // dns
+            255, 255, 255, 0, // subnet mask
+            0x80, 0x51, 0x01, 0x00, // lease time
+        ];
+        let config = IpConfig::from(data);
+        assert_eq!(config.ip, Ipv4Addr::new(192, 168, 1, 42));
+        assert_eq!(config.gateway, Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(config.dns, Ipv4Addr::new(8, 8, 8, 8));
+        assert_eq!(config.subnet_mask, Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(config.lease_time, 86400);
+    }
+
+    #[test]
+    fn scan_region_from_country() {
+        assert!(matches!(
+            ScanRegion::from_country(*b"US"),
+            Ok(ScanRegion::NorthAmerica)
+        ));
+        assert!(matches!(
+            ScanRegion::from_country(*b"jp"),
+            Ok(ScanRegion::Asia)
+        ));
+        assert!(matches!(
+            ScanRegion::from_country(*b"DE"),
+            Ok(ScanRegion::Europe)
+        ));
+        assert!(matches!(
+            ScanRegion::from_country(*b"1!"),
+            Err(Error::InvalidCountryCode)
+        ));
+    }
+
+    #[test]
+    fn save_credentials_byte() {
+        // The firmware field is "no save credentials"
+        let save =
+            ConnectionParameters::open(b"network", Channel::Any, SaveCredentials::SaveToFlash)
+                .unwrap();
+        let dont_save =
+            ConnectionParameters::open(b"network", Channel::Any, SaveCredentials::default())
+                .unwrap();
+        assert_eq!(OldConnection::from(&save)[103], 0);
+        assert_eq!(OldConnection::from(&dont_save)[103], 1);
+    }
+
+    #[test]
+    fn scan_result_ssid() {
+        let result = ScanResult::from(scan_result_bytes(6));
+        assert_eq!(result.ssid_bytes(), b"network");
+        assert_eq!(result.ssid_str(), Some("network"));
+        assert!(result
+            .to_string()
+            .starts_with("network (f8:f0:05:01:02:03)"));
+    }
+
+    #[test]
+    fn rssi_quality() {
+        assert_eq!(rssi_to_quality(-30), 100);
+        assert_eq!(rssi_to_quality(-50), 100);
+        assert_eq!(rssi_to_quality(-75), 50);
+        assert_eq!(rssi_to_quality(-99), 2);
+        assert_eq!(rssi_to_quality(-100), 0);
+        assert_eq!(rssi_to_quality(i8::MIN), 0);
+        let mut data = scan_result_bytes(6);
+        data[1] = (-60_i8) as u8;
+        assert_eq!(ScanResult::from(data).quality(), 80);
+    }
+
+    #[test]
+    fn scan_result_security_type() {
+        let mut data = scan_result_bytes(6);
+        let result = ScanResult::from(data);
+        assert_eq!(result.security_type(), Some(SecurityType::WpaPsk));
+        assert!(result.requires_password());
+        assert!(!result.is_enterprise());
+        data[2] = 4;
+        let result = ScanResult::from(data);
+        assert!(result.requires_password());
+        assert!(result.is_enterprise());
+        data[2] = 1;
+        assert!(!ScanResult::from(data).requires_password());
+        data[2] = 0;
+        let result = ScanResult::from(data);
+        assert_eq!(result.security_type(), None);
+        assert!(!result.requires_password());
+        assert!(!result.is_enterprise());
+    }
+
+    #[test]
+    fn scan_result_invalid_utf8_ssid() {
+        let mut data = scan_result_bytes(6);
+        data[10..17].copy_from_slice(&[0xe9, b't', b'e', 0, 0, 0, 0]);
+        let result = ScanResult::from(data);
+        assert_eq!(result.ssid_str(), None);
+        assert!(result.to_string().starts_with("e97465 "));
+    }
+
+    #[test]
+    fn scan_result_try_from() {
+        let data = scan_result_bytes(6);
+        assert!(ScanResult::try_from(&data[..]).is_ok());
+        assert!(matches!(
+            ScanResult::try_from(&data[..20]),
+            Err(Error::HifError(HifError::InvalidFrame))
+        ));
+        let bad_channel = scan_result_bytes(42);
+        assert!(ScanResult::try_from(&bad_channel[..]).is_err());
+        let mut unterminated = scan_result_bytes(6);
+        unterminated[10..43].copy_from_slice(&[b'a'; 33]);
+        assert!(ScanResult::try_from(&unterminated[..]).is_err());
+    }
+
+    #[test]
+    fn state_change_try_from() {
+        let change = StateChange::try_from(&[1, 0, 0, 0][..]).unwrap();
+        assert!(change.connected);
+        let change = StateChange::try_from(&[0, 3, 0, 0][..]).unwrap();
+        assert!(!change.connected);
+        assert_eq!(change.error_code, StateChangeErrorCode::AuthFail);
+        assert!(StateChange::try_from(&[200, 0, 0, 0][..]).is_err());
+        let change = StateChange::try_from(&[0, 42, 0, 0][..]).unwrap();
+        assert_eq!(change.error_code, StateChangeErrorCode::Unknown);
+        assert!(StateChange::try_from(&[1, 0][..]).is_err());
+    }
+
+    #[test]
+    fn connection_parameters_bytes() {
+        let save = SaveCredentials::SaveToFlash;
+        let params =
+            ConnectionParameters::wpa_psk(b"network", b"password", Channel::Ch6, save).unwrap();
+        let data = params.to_bytes().unwrap();
+        assert_eq!(data[0], CONNECTION_BYTES_VERSION);
+        assert_eq!(&data[4..12], b"network\0");
+        let restored = ConnectionParameters::from_bytes(&data).unwrap();
+        assert_eq!(OldConnection::from(&restored), OldConnection::from(&params));
+        let open = ConnectionParameters::open(b"network", Channel::Any, save).unwrap();
+        let restored = ConnectionParameters::from_bytes(&open.to_bytes().unwrap()).unwrap();
+        assert_eq!(OldConnection::from(&restored), OldConnection::from(&open));
+        assert_eq!(restored.bssid(), None);
+        // Version 1 has no bssid
+        let mut v1 = [0; 102];
+        v1.copy_from_slice(&data[..102]);
+        v1[0] = 1;
+        let restored = ConnectionParameters::from_bytes(&v1).unwrap();
+        assert_eq!(OldConnection::from(&restored), OldConnection::from(&params));
+        let bssid = MacAddress([0xf8, 0xf0, 0x05, 0x01, 0x02, 0x03]);
+        let locked = params.clone().with_bssid(bssid);
+        let restored = ConnectionParameters::from_bytes(&locked.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.bssid(), Some(bssid));
+        let mut newer = data;
+        newer[0] = CONNECTION_BYTES_VERSION + 1;
+        let mut unterminated = data;
+        unterminated[36] = b'a';
+        for bad in [&newer[..], &unterminated[..], &data[..50]] {
+            assert!(matches!(
+                ConnectionParameters::from_bytes(bad),
+                Err(Error::InvalidConnectionBytes)
+            ));
+        }
+    }
+
+    #[test]
+    fn connection_parameters_too_long() {
+        let save = SaveCredentials::default();
+        assert!(ConnectionParameters::open(&[b'a'; 32], Channel::Any, save).is_ok());
+        assert!(matches!(
+            ConnectionParameters::open(&[b'a'; 33], Channel::Any, save),
+            Err(Error::SsidTooLong)
+        ));
+        assert!(ConnectionParameters::wpa_psk(b"network", &[b'a'; 64], Channel::Any, save).is_ok());
+        assert!(matches!(
+            ConnectionParameters::wpa_psk(b"network", &[b'a'; 65], Channel::Any, save),
+            Err(Error::PassphraseTooLong)
+        ));
+    }
+
+    #[test]
+    fn open_connection_layout() {
+        let connection =
+            ConnectionParameters::open(b"network", Channel::Ch6, SaveCredentials::default())
+                .unwrap();
+        let header = OldConnection::from(&connection);
+        assert_eq!(header.len(), 108);
+        // No passphrase
+        assert!(header[0..65].iter().all(|b| *b == 0));
+        // Security type and padding
+        assert_eq!(header[65..68], [1, 0, 0]);
+        // Channel (2 bytes, little endian)
+        assert_eq!(header[68..70], [6, 0]);
+        // Null terminated ssid
+        assert_eq!(&header[70..77], b"network");
+        assert!(header[77..103].iter().all(|b| *b == 0));
+        // No save credentials
+        assert_eq!(header[103], 1);
+        // Padding
+        assert_eq!(header[104..108], [0; 4]);
+    }
+
+    #[test]
+    fn wpa_psk_connection_layout() {
+        let connection = ConnectionParameters::wpa_psk(
+            b"network",
+            b"passphrase",
+            Channel::Any,
+            SaveCredentials::SaveToFlash,
+        )
+        .unwrap();
+        let header = OldConnection::from(&connection);
+        // Null terminated passphrase
+        assert_eq!(&header[0..10], b"passphrase");
+        assert!(header[10..65].iter().all(|b| *b == 0));
+        // Security type and padding
+        assert_eq!(header[65..68], [2, 0, 0]);
+        // Channel (2 bytes, little endian)
+        assert_eq!(header[68..70], [255, 0]);
+        // Null terminated ssid
+        assert_eq!(&header[70..77], b"network");
+        assert!(header[77..103].iter().all(|b| *b == 0));
+        // Save credentials
+        assert_eq!(header[103], 0);
+        // Padding
+        assert_eq!(header[104..108], [0; 4]);
+    }
+
+    #[test]
+    fn unimplemented_security_types() {
+        assert!(matches!(
+            ConnectionParameters::_wep(),
+            Err(Error::NotImplemented)
+        ));
+        assert!(matches!(
+            ConnectionParameters::_wpa_enterprise(),
+            Err(Error::NotImplemented)
+        ));
+    }
+}